@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use log::{error, info, warn};
+use serenity::model::id::UserId;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use rspotify::{
+    AuthCodeSpotify,
+    Config,
+    Credentials,
+    OAuth,
+    scopes,
+    clients::BaseClient,
+    clients::OAuthClient,
+};
+
+use crate::CommandError;
+
+// Commands that act on the caller's own account in guilds using linked accounts
+pub fn uses_linked_account(command: &str) -> bool {
+    matches!(command, "search" | "play" | "queue add" | "status")
+}
+
+// how long a /link login stays usable
+const LINK_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+// Spotify accounts linked by individual discord users with /link. Tokens are
+// kept in one file per user so links survive restarts.
+pub struct Accounts {
+    creds: Credentials,
+    redirect_uri: String,
+    token_dir: PathBuf,
+    linked: Mutex<HashMap<UserId, Arc<AuthCodeSpotify>>>,
+    // clients waiting on the oauth redirect, keyed by their oauth state
+    pending: Mutex<HashMap<String, (UserId, AuthCodeSpotify, Instant)>>,
+}
+
+impl Accounts {
+    pub fn new(creds: Credentials, redirect_uri: String, token_dir: PathBuf) -> Self {
+        Accounts {
+            creds,
+            redirect_uri,
+            token_dir,
+            linked: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn token_path(&self, user: UserId) -> PathBuf {
+        self.token_dir.join(format!("{}.json", user))
+    }
+
+    fn client(&self, user: UserId) -> AuthCodeSpotify {
+        let config = Config {
+            token_refreshing: true,
+            token_cached: true,
+            cache_path: self.token_path(user),
+            ..Default::default()
+        };
+
+        let oauth = OAuth {
+            redirect_uri: self.redirect_uri.clone(),
+            scopes: scopes!("user-read-playback-state", "user-modify-playback-state"),
+            ..Default::default()
+        };
+
+        AuthCodeSpotify::with_config(self.creds.clone(), oauth, config)
+    }
+
+    // The account `user` has linked, loading it from their token file if
+    // needed. None if they haven't linked one or the link has gone stale.
+    pub async fn get(&self, user: UserId) -> Result<Option<Arc<AuthCodeSpotify>>, CommandError> {
+        // not held while talking to spotify, so one slow refresh doesn't hold up
+        // everyone else
+        let cached = self.linked.lock().await.get(&user).cloned();
+
+        let spotify = match &cached {
+            Some(spotify) => spotify.clone(),
+            None => {
+                let spotify = self.client(user);
+                match spotify.read_token_cache(true).await {
+                    Ok(Some(token)) => *spotify.get_token().lock().await.unwrap() = Some(token),
+                    _ => return Ok(None),
+                }
+                Arc::new(spotify)
+            }
+        };
+
+        // refresh here rather than letting a request fail on a revoked token
+        let expired = spotify
+            .get_token()
+            .lock()
+            .await
+            .unwrap()
+            .as_ref()
+            .map(|token| token.is_expired())
+            .unwrap_or(true);
+
+        if expired {
+            match spotify.refetch_token().await {
+                Ok(Some(token)) => {
+                    *spotify.get_token().lock().await.unwrap() = Some(token);
+                    spotify.write_token_cache().await?;
+                }
+                _ => {
+                    warn!("Spotify link for {} could not be refreshed", user);
+                    let mut linked = self.linked.lock().await;
+                    // unless it was relinked in the meantime
+                    if matches!(linked.get(&user), Some(current) if Arc::ptr_eq(current, &spotify)) {
+                        linked.remove(&user);
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+
+        if cached.is_none() {
+            self.linked.lock().await.entry(user).or_insert_with(|| spotify.clone());
+        }
+
+        Ok(Some(spotify))
+    }
+
+    // Authorize url for `user` to open, replacing any link they had started
+    pub async fn start_link(&self, user: UserId) -> Result<String, CommandError> {
+        fs::create_dir_all(&self.token_dir).map_err(rspotify::ClientError::from)?;
+
+        let spotify = self.client(user);
+        let url = spotify.get_authorize_url(false)?;
+
+        let mut pending = self.pending.lock().await;
+        // also forget links nobody finished
+        pending.retain(|_, (pending_user, _, started)| *pending_user != user && started.elapsed() < LINK_TIMEOUT);
+        pending.insert(spotify.oauth.state.clone(), (user, spotify, Instant::now()));
+
+        Ok(url)
+    }
+
+    // Swap the code from the oauth redirect for a token, returning who linked
+    async fn finish_link(&self, state: &str, code: &str) -> Result<UserId, CommandError> {
+        let (user, spotify, started) = self.pending
+            .lock()
+            .await
+            .remove(state)
+            .ok_or("This link has expired, run /link again")?;
+
+        if started.elapsed() >= LINK_TIMEOUT {
+            return Err(CommandError::from("This link has expired, run /link again"));
+        }
+
+        // writes the token file too
+        spotify.request_token(code).await?;
+
+        self.linked.lock().await.insert(user, Arc::new(spotify));
+        info!("Linked spotify account for {}", user);
+
+        Ok(user)
+    }
+
+    // Forget the account `user` linked. Returns false if there wasn't one.
+    pub async fn unlink(&self, user: UserId) -> bool {
+        let was_linked = self.linked.lock().await.remove(&user).is_some();
+
+        match fs::remove_file(self.token_path(user)) {
+            Ok(()) => true,
+            Err(_) => was_linked,
+        }
+    }
+}
+
+async fn handle(
+    request: Request<Body>,
+    path: Arc<str>,
+    accounts: Arc<Accounts>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != &*path {
+        return Ok(page(StatusCode::NOT_FOUND, "Not found"));
+    }
+
+    let query = request.uri().query().unwrap_or_default();
+    let mut code = None;
+    let mut state = None;
+
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            "code" => code = Some(value.into_owned()),
+            "state" => state = Some(value.into_owned()),
+            _ => (),
+        }
+    }
+
+    let (code, state) = match (code, state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return Ok(page(StatusCode::BAD_REQUEST, "Spotify login was cancelled.")),
+    };
+
+    match accounts.finish_link(&state, &code).await {
+        Ok(_) => Ok(page(StatusCode::OK, "Your spotify account is linked, you can close this tab.")),
+        Err(why) => Ok(page(StatusCode::BAD_REQUEST, &String::from(why))),
+    }
+}
+
+fn page(code: StatusCode, text: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(text.to_string()));
+    *response.status_mut() = code;
+    response
+}
+
+// Listen for spotify redirecting users back after they log in through /link
+pub fn spawn_listener(accounts: Arc<Accounts>, port: u16, path: String) -> JoinHandle<()> {
+    let path: Arc<str> = path.into();
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+
+    tokio::spawn(async move {
+        let make_service = make_service_fn(move |_connection| {
+            let path = path.clone();
+            let accounts = accounts.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    handle(request, path.clone(), accounts.clone())
+                }))
+            }
+        });
+
+        let server = match Server::try_bind(&address) {
+            Ok(server) => server,
+            Err(why) => {
+                error!("Failed to bind account link listener on {}: {}", address, why);
+                return;
+            }
+        };
+
+        info!("Listening for spotify account links on {}", address);
+
+        if let Err(why) = server.serve(make_service).await {
+            error!("Account link listener error: {}", why);
+        }
+    })
+}
@@ -0,0 +1,280 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use log::{error, info, warn};
+use serenity::http::Http;
+use serenity::model::id::UserId;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use url::Url;
+
+use rspotify::{
+    AuthCodeSpotify,
+    ClientError,
+    clients::BaseClient,
+    clients::OAuthClient,
+};
+
+// How often the token keeper checks the token, and how long before it expires
+// it gets refreshed
+const KEEPER_INTERVAL: Duration = Duration::from_secs(60);
+const REFRESH_MARGIN: i64 = 5 * 60;
+
+// how long the admin has to finish logging in before the attempt is given up
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug)]
+pub enum AuthError {
+    Spotify(ClientError),
+    Discord(serenity::Error),
+    Listener(hyper::Error),
+    InvalidRedirect(String),
+    TimedOut,
+}
+
+impl From<ClientError> for AuthError {
+    fn from(error: ClientError) -> Self {
+        AuthError::Spotify(error)
+    }
+}
+
+impl From<serenity::Error> for AuthError {
+    fn from(error: serenity::Error) -> Self {
+        AuthError::Discord(error)
+    }
+}
+
+impl From<hyper::Error> for AuthError {
+    fn from(error: hyper::Error) -> Self {
+        AuthError::Listener(error)
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Spotify(error) => write!(f, "spotify error: {}", error),
+            AuthError::Discord(error) => write!(f, "discord error: {}", error),
+            AuthError::Listener(error) => write!(f, "redirect listener error: {}", error),
+            AuthError::InvalidRedirect(error) => write!(f, "invalid redirect uri: {}", error),
+            AuthError::TimedOut => write!(f, "nobody logged in within {} minutes", LOGIN_TIMEOUT.as_secs() / 60),
+        }
+    }
+}
+
+// Who to message with the authorize link when nobody is at a terminal
+#[derive(Clone)]
+pub struct Admin {
+    pub http: Arc<Http>,
+    pub user: UserId,
+}
+
+// Load the cached token, refreshing it if it has expired. Without a usable
+// token the admin is sent an authorize link over discord, or without an admin
+// the link is printed to the terminal as before.
+pub async fn authenticate(spotify: &AuthCodeSpotify, admin: Option<&Admin>) -> Result<(), AuthError> {
+    match spotify.read_token_cache(true).await {
+        Ok(Some(token)) => {
+            let expired = token.is_expired();
+            *spotify.get_token().lock().await.unwrap() = Some(token);
+
+            if !expired || refresh(spotify).await {
+                info!("Loaded spotify token from {}", spotify.config.cache_path.display());
+                return Ok(());
+            }
+        }
+        Ok(None) => info!("No usable spotify token cached"),
+        Err(why) => info!("Could not read spotify token cache: {}", why),
+    }
+
+    reauthorize(spotify, admin).await
+}
+
+// Ask for a fresh authorization, through the admin if there is one
+async fn reauthorize(spotify: &AuthCodeSpotify, admin: Option<&Admin>) -> Result<(), AuthError> {
+    match admin {
+        Some(admin) => headless(spotify, admin).await,
+        None => {
+            let url = spotify.get_authorize_url(false)?;
+            let code = spotify.get_code_from_user(&url)?;
+            spotify.request_token(&code).await?;
+            Ok(())
+        }
+    }
+}
+
+// Swap the refresh token for a new access token, writing it back to the cache.
+// Returns false if spotify would not hand one out.
+async fn refresh(spotify: &AuthCodeSpotify) -> bool {
+    match spotify.refetch_token().await {
+        Ok(Some(token)) => {
+            *spotify.get_token().lock().await.unwrap() = Some(token);
+            if let Err(why) = spotify.write_token_cache().await {
+                warn!("Failed to write spotify token cache: {}", why);
+            }
+            true
+        }
+        Ok(None) => {
+            warn!("No spotify refresh token to use");
+            false
+        }
+        Err(why) => {
+            warn!("Failed to refresh spotify token: {}", why);
+            false
+        }
+    }
+}
+
+// DM the authorize link to the admin and wait for spotify to redirect their
+// browser back to the listener on the redirect uri
+async fn headless(spotify: &AuthCodeSpotify, admin: &Admin) -> Result<(), AuthError> {
+    let redirect = Url::parse(&spotify.oauth.redirect_uri)
+        .map_err(|why| AuthError::InvalidRedirect(why.to_string()))?;
+    let port = redirect
+        .port_or_known_default()
+        .ok_or_else(|| AuthError::InvalidRedirect("no port".to_string()))?;
+    let path = redirect.path().to_string();
+    let state = spotify.oauth.state.clone();
+
+    let (codes, mut code) = mpsc::channel::<String>(1);
+
+    let make_service = make_service_fn(move |_connection| {
+        let path = path.clone();
+        let state = state.clone();
+        let codes = codes.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(request, path.clone(), state.clone(), codes.clone())
+            }))
+        }
+    });
+
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let server = Server::try_bind(&address)?;
+
+    let url = spotify.get_authorize_url(false)?;
+    let channel = admin.user.create_dm_channel(&admin.http).await?;
+    channel
+        .say(&admin.http, format!("Fishify needs to be reconnected to spotify, open this link to log in: {url}"))
+        .await?;
+
+    info!("Sent spotify authorize link to admin, waiting for redirect on {}", address);
+
+    let server = server.serve(make_service);
+    let redirect = async {
+        tokio::select! {
+            result = server => {
+                result?;
+                Err(AuthError::InvalidRedirect("redirect listener stopped".to_string()))
+            }
+            code = code.recv() => Ok(code.expect("redirect listener holds a sender")),
+        }
+    };
+
+    // dropping the listener on timeout frees the port for the next attempt
+    let code = match tokio::time::timeout(LOGIN_TIMEOUT, redirect).await {
+        Ok(code) => code?,
+        Err(_) => {
+            if let Err(why) = channel.say(&admin.http, "The spotify login link has expired").await {
+                warn!("Failed to tell admin the spotify login expired: {}", why);
+            }
+            return Err(AuthError::TimedOut);
+        }
+    };
+
+    spotify.request_token(&code).await?;
+
+    if let Err(why) = channel.say(&admin.http, "Fishify is connected to spotify again").await {
+        warn!("Failed to confirm spotify login to admin: {}", why);
+    }
+
+    Ok(())
+}
+
+async fn handle(
+    request: Request<Body>,
+    path: String,
+    state: String,
+    codes: mpsc::Sender<String>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != path {
+        return Ok(page(StatusCode::NOT_FOUND, "Not found"));
+    }
+
+    let query = request.uri().query().unwrap_or_default();
+    let mut code = None;
+    let mut returned_state = None;
+    let mut denied = None;
+
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            "code" => code = Some(value.into_owned()),
+            "state" => returned_state = Some(value.into_owned()),
+            "error" => denied = Some(value.into_owned()),
+            _ => (),
+        }
+    }
+
+    if let Some(reason) = denied {
+        warn!("Spotify authorization was denied: {}", reason);
+        return Ok(page(StatusCode::BAD_REQUEST, "Spotify login was cancelled, use the link again to retry."));
+    }
+
+    if returned_state.as_deref() != Some(state.as_str()) {
+        warn!("Rejected spotify redirect with wrong state");
+        return Ok(page(StatusCode::BAD_REQUEST, "This login link has expired."));
+    }
+
+    match code {
+        Some(code) => {
+            // a full channel means a code was already received
+            let _ = codes.try_send(code);
+            Ok(page(StatusCode::OK, "Fishify is connected to spotify, you can close this tab."))
+        }
+        None => Ok(page(StatusCode::BAD_REQUEST, "Missing authorization code.")),
+    }
+}
+
+fn page(code: StatusCode, text: &'static str) -> Response<Body> {
+    let mut response = Response::new(Body::from(text));
+    *response.status_mut() = code;
+    response
+}
+
+// Refresh the token a little before it expires, so requests never have to.
+// If the refresh token stops working the authorization flow runs again.
+pub fn spawn_token_keeper(spotify: Arc<AuthCodeSpotify>, admin: Option<Admin>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(KEEPER_INTERVAL).await;
+
+            let expiring = spotify
+                .get_token()
+                .lock()
+                .await
+                .unwrap()
+                .as_ref()
+                .and_then(|token| token.expires_at)
+                .map(|expires_at| expires_at - chrono::Utc::now() < chrono::Duration::seconds(REFRESH_MARGIN))
+                .unwrap_or(true);
+
+            if !expiring || refresh(&spotify).await {
+                continue;
+            }
+
+            if admin.is_none() {
+                error!("Spotify token could not be refreshed and no admin is set to reauthorize");
+                continue;
+            }
+
+            if let Err(why) = reauthorize(&spotify, admin.as_ref()).await {
+                error!("Spotify reauthorization failed: {}", why);
+            }
+        }
+    })
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serenity::model::id::UserId;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::CommandDataOption;
+use tokio::sync::Mutex;
+
+use rspotify::{
+    model::enums::types::SearchType,
+    model::search::SearchResult,
+};
+
+use crate::CommandError;
+use crate::TypeFromStr;
+use crate::backend::SpotifyBackend;
+use crate::response::truncate;
+
+// how long to wait for someone to stop typing before searching
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+// how long suggestions are reused for
+const SEARCH_TTL: Duration = Duration::from_secs(5 * 60);
+const DEVICE_TTL: Duration = Duration::from_secs(10);
+
+const MAX_CACHED: usize = 500;
+
+// Discord's limits on autocomplete choices
+const MAX_CHOICES: usize = 25;
+const MAX_CHOICE_LENGTH: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub name: String,
+    pub value: String,
+}
+
+// when a search was cached and what it suggested
+type CachedSearch = (Instant, Vec<Suggestion>);
+
+// Suggestions for the `name` options of play, queue, search and connect.
+// Discord asks on every keystroke, so searches are debounced per user and
+// their results cached.
+pub struct Autocomplete {
+    // the newest request from each user, older ones give up after the debounce
+    latest: Mutex<HashMap<UserId, u64>>,
+    // keyed by search type and lowercased name
+    searches: Mutex<HashMap<(String, String), CachedSearch>>,
+    devices: Mutex<Option<(Instant, Vec<String>)>>,
+}
+
+impl Autocomplete {
+    pub fn new() -> Self {
+        Autocomplete {
+            latest: Mutex::new(HashMap::new()),
+            searches: Mutex::new(HashMap::new()),
+            devices: Mutex::new(None),
+        }
+    }
+
+    // Suggestions for the option being typed into, or None if a newer
+    // request from the same user replaced this one
+    pub async fn suggest<S: SpotifyBackend + ?Sized>(
+        &self,
+        spotify: &S,
+        command: &str,
+        options: &[CommandDataOption],
+        user: UserId,
+    ) -> Result<Option<Vec<Suggestion>>, CommandError> {
+        // subcommands carry their own options
+        let options = match options.first() {
+            Some(option) if option.kind == CommandOptionType::SubCommand => &option.options[..],
+            _ => options,
+        };
+
+        let focused = match options.iter().find(|option| option.focused) {
+            Some(option) if option.name == "name" => option,
+            _ => return Ok(Some(vec![])),
+        };
+
+        let typed = focused.value
+            .as_ref()
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .trim();
+
+        if command == "connect" {
+            return Ok(Some(self.devices(spotify, typed).await?));
+        }
+
+        if typed.is_empty() {
+            return Ok(Some(vec![]));
+        }
+
+        let type_name = string_option(options, "type").unwrap_or("track");
+        let search_type = SearchType::parse(type_name)?;
+
+        let key = (type_name.to_string(), typed.to_lowercase());
+
+        if let Some((cached_at, suggestions)) = self.searches.lock().await.get(&key) {
+            if cached_at.elapsed() < SEARCH_TTL {
+                return Ok(Some(suggestions.clone()));
+            }
+        }
+
+        if !self.debounce(user).await {
+            return Ok(None);
+        }
+
+        let result = spotify.search(typed, search_type, Some(MAX_CHOICES as u32), None).await?;
+        let suggestions = suggestions(result);
+
+        let mut searches = self.searches.lock().await;
+        searches.retain(|_, (cached_at, _)| cached_at.elapsed() < SEARCH_TTL);
+        if searches.len() < MAX_CACHED {
+            searches.insert(key, (Instant::now(), suggestions.clone()));
+        }
+
+        Ok(Some(suggestions))
+    }
+
+    // Wait out the debounce, returning whether this is still the user's
+    // newest request
+    async fn debounce(&self, user: UserId) -> bool {
+        let id = {
+            let mut latest = self.latest.lock().await;
+            let id = latest.get(&user).map_or(0, |id| id + 1);
+            latest.insert(user, id);
+            id
+        };
+
+        tokio::time::sleep(DEBOUNCE).await;
+
+        self.latest.lock().await.get(&user) == Some(&id)
+    }
+
+    async fn devices<S: SpotifyBackend + ?Sized>(&self, spotify: &S, typed: &str) -> Result<Vec<Suggestion>, CommandError> {
+        let mut devices = self.devices.lock().await;
+
+        let names = match devices.as_ref() {
+            Some((cached_at, names)) if cached_at.elapsed() < DEVICE_TTL => names.clone(),
+            _ => {
+                let names: Vec<String> = spotify.device().await?
+                    .into_iter()
+                    .map(|device| device.name)
+                    .collect();
+                *devices = Some((Instant::now(), names.clone()));
+                names
+            }
+        };
+
+        let typed = typed.to_lowercase();
+
+        Ok(names
+            .into_iter()
+            .filter(|name| name.to_lowercase().contains(&typed))
+            // connect matches the exact name, so one that doesn't fit can't be offered
+            .filter(|name| name.chars().count() <= MAX_CHOICE_LENGTH)
+            .take(MAX_CHOICES)
+            .map(|name| Suggestion { name: name.clone(), value: name })
+            .collect())
+    }
+}
+
+fn string_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options.iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+}
+
+// The commands search spotify for the submitted name, so each suggestion
+// fills in a name and artist that finds the suggested item again
+fn suggestions(result: SearchResult) -> Vec<Suggestion> {
+    let found: Vec<(String, Option<String>)> = match result {
+        SearchResult::Tracks(page) => page.items
+            .into_iter()
+            .map(|item| (item.name, item.artists.into_iter().next().map(|artist| artist.name)))
+            .collect(),
+        SearchResult::Albums(page) => page.items
+            .into_iter()
+            .map(|item| (item.name, item.artists.into_iter().next().map(|artist| artist.name)))
+            .collect(),
+        SearchResult::Playlists(page) => page.items
+            .into_iter()
+            .map(|item| (item.name, None))
+            .collect(),
+        SearchResult::Artists(page) => page.items
+            .into_iter()
+            .map(|item| (item.name, None))
+            .collect(),
+        _ => vec![],
+    };
+
+    found.into_iter()
+        .take(MAX_CHOICES)
+        .map(|(name, artist)| match artist {
+            Some(artist) => Suggestion {
+                name: truncate(&format!("{} \u{2014} {}", name, artist), MAX_CHOICE_LENGTH),
+                value: format!("{} {}", name, artist).chars().take(MAX_CHOICE_LENGTH).collect(),
+            },
+            None => Suggestion {
+                value: name.chars().take(MAX_CHOICE_LENGTH).collect(),
+                name: truncate(&name, MAX_CHOICE_LENGTH),
+            },
+        })
+        .collect()
+}
@@ -0,0 +1,818 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+
+use chrono::Utc;
+use reqwest::ResponseBuilderExt;
+use serenity::async_trait;
+use url::Url;
+
+use rspotify::{
+    ClientError,
+    ClientResult,
+    http::HttpError,
+    model::enums::types::AlbumType,
+    model::enums::types::CurrentlyPlayingType,
+    model::enums::types::DeviceType,
+    model::enums::types::SearchType,
+    model::enums::types::Type,
+    model::enums::misc::DatePrecision,
+    model::enums::misc::RepeatState,
+    model::search::SearchResult,
+    model::album::FullAlbum,
+    model::album::SimplifiedAlbum,
+    model::artist::FullArtist,
+    model::artist::SimplifiedArtist,
+    model::playlist::FullPlaylist,
+    model::playlist::PlaylistItem,
+    model::playlist::SimplifiedPlaylist,
+    model::playlist::PlaylistTracksRef,
+    model::track::FullTrack,
+    model::track::SimplifiedTrack,
+    model::user::PublicUser,
+    model::device::Device,
+    model::context::Actions,
+    model::context::Context,
+    model::context::CurrentPlaybackContext,
+    model::context::CurrentUserQueue,
+    model::idtypes::AlbumId,
+    model::idtypes::ArtistId,
+    model::idtypes::PlaylistId,
+    model::idtypes::TrackId,
+    model::idtypes::UserId,
+    model::page::Page,
+    model::Followers,
+    model::PlayableItem,
+    prelude::Id,
+    prelude::PlayContextId,
+    prelude::PlayableId,
+};
+
+use super::SpotifyBackend;
+
+// Everything the fake knows about. Fields are public so tests can set up and
+// inspect state directly through `FakeSpotify::state`.
+#[derive(Debug)]
+pub struct FakeState {
+    pub tracks: Vec<FullTrack>,
+    pub albums: Vec<FullAlbum>,
+    pub playlists: Vec<FullPlaylist>,
+    pub artists: Vec<FullArtist>,
+    pub devices: Vec<Device>,
+    pub item: Option<FullTrack>,
+    pub context: Option<Context>,
+    pub context_tracks: Vec<FullTrack>,
+    pub context_position: usize,
+    pub queue: VecDeque<FullTrack>,
+    pub progress: Duration,
+    pub is_playing: bool,
+    pub repeat_state: RepeatState,
+    pub shuffle_state: bool,
+    // statuses the next requests fail with, in order, whatever they ask for
+    pub failures: VecDeque<u16>,
+    // what rate limited requests are told to wait
+    pub retry_after: u64,
+}
+
+// In-memory stand in for the spotify api
+#[derive(Debug)]
+pub struct FakeSpotify {
+    state: Mutex<FakeState>,
+}
+
+impl Default for FakeSpotify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeSpotify {
+    pub fn new() -> Self {
+        FakeSpotify {
+            state: Mutex::new(FakeState {
+                tracks: vec![],
+                albums: vec![],
+                playlists: vec![],
+                artists: vec![],
+                devices: vec![],
+                item: None,
+                context: None,
+                context_tracks: vec![],
+                context_position: 0,
+                queue: VecDeque::new(),
+                progress: Duration::ZERO,
+                is_playing: false,
+                repeat_state: RepeatState::Off,
+                shuffle_state: false,
+                failures: VecDeque::new(),
+                retry_after: 1,
+            }),
+        }
+    }
+
+    // Small catalog with a single active device, used when running the bot
+    // without a spotify account
+    pub fn with_demo_catalog() -> Self {
+        let fake = Self::new();
+
+        let tracks = vec![
+            track("4uLU6hMCjMI75M1A2tKUQC", "Never Gonna Give You Up", "Rick Astley", 213),
+            track("7GhIk7Il098yCjg4BQjzvb", "Together Forever", "Rick Astley", 205),
+            track("0b11D9D0hMOYCIMN3OKreM", "Blue (Da Ba Dee)", "Eiffel 65", 283),
+        ];
+
+        fake.add_album(album("6XhjNHCyCDyyGJRM5mg40G", "Whenever You Need Somebody", "Rick Astley", &tracks[..2]));
+        fake.add_playlist(playlist("37i9dQZF1DXcBWIGoYBM5M", "Fishify Demo", "fishify", &tracks));
+        fake.add_artist(artist("0gxyHStUsqpMadRV0Di1Qt", "Rick Astley"));
+        for track in tracks {
+            fake.add_track(track);
+        }
+
+        let mut speaker = device("fakespeaker0", "Fake Speaker");
+        speaker.is_active = true;
+        fake.add_device(speaker);
+
+        fake
+    }
+
+    // The demo catalog with its album playing from the first track
+    #[cfg(test)]
+    pub async fn playing_demo_album() -> Self {
+        let fake = Self::with_demo_catalog();
+        let album = AlbumId::from_id("6XhjNHCyCDyyGJRM5mg40G").expect("invalid demo album id");
+        fake.start_context_playback(PlayContextId::Album(album), None).await.expect("demo album failed to play");
+        fake
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().expect("fake spotify state poisoned")
+    }
+
+    // Lock the state for a request to `path`, unless it is meant to fail
+    fn request(&self, path: &str) -> ClientResult<MutexGuard<'_, FakeState>> {
+        let mut state = self.state();
+        match state.failures.pop_front() {
+            Some(429) => Err(rate_limit_error(429, path, "API rate limit exceeded", Some(state.retry_after))),
+            Some(status) => Err(api_error(status, path, "Failed by the fake")),
+            None => Ok(state),
+        }
+    }
+
+    pub fn add_track(&self, track: FullTrack) {
+        self.state().tracks.push(track);
+    }
+
+    pub fn add_album(&self, album: FullAlbum) {
+        self.state().albums.push(album);
+    }
+
+    pub fn add_playlist(&self, playlist: FullPlaylist) {
+        self.state().playlists.push(playlist);
+    }
+
+    pub fn add_artist(&self, artist: FullArtist) {
+        self.state().artists.push(artist);
+    }
+
+    pub fn add_device(&self, device: Device) {
+        self.state().devices.push(device);
+    }
+}
+
+impl FakeState {
+    fn find_track(&self, id: &str) -> ClientResult<FullTrack> {
+        self.tracks
+            .iter()
+            .find(|track| track.id.as_ref().map(|track_id| track_id.id()) == Some(id))
+            .cloned()
+            .ok_or_else(|| api_error(404, "/tracks", "Non existing id"))
+    }
+
+    // Mirrors spotify returning 404 when there is nothing to control
+    fn active_device(&mut self, device_id: Option<&str>) -> ClientResult<&mut Device> {
+        self.devices
+            .iter_mut()
+            .find(|device| match device_id {
+                Some(id) => device.id.as_deref() == Some(id),
+                None => device.is_active,
+            })
+            .ok_or_else(|| api_error(404, "/me/player", "Player command failed: No active device found"))
+    }
+
+    fn play_list(&mut self, tracks: Vec<FullTrack>, context: Option<Context>) -> ClientResult<()> {
+        let first = tracks
+            .first()
+            .cloned()
+            .ok_or_else(|| api_error(400, "/me/player/play", "Nothing to play"))?;
+
+        self.item = Some(first);
+        self.context = context;
+        self.context_tracks = tracks;
+        self.context_position = 0;
+        self.progress = Duration::ZERO;
+        self.is_playing = true;
+        Ok(())
+    }
+
+    // Like spotify, go back a track only near the start of one, otherwise
+    // start it over
+    fn rewind(&mut self) {
+        let near_start = self.progress < Duration::from_secs(3);
+        self.progress = Duration::ZERO;
+
+        if near_start && self.context_position > 0 {
+            if let Some(previous) = self.context_tracks.get(self.context_position - 1) {
+                self.item = Some(previous.clone());
+                self.context_position -= 1;
+            }
+        }
+    }
+
+    fn advance(&mut self) {
+        self.progress = Duration::ZERO;
+
+        if let Some(next) = self.queue.pop_front() {
+            self.item = Some(next);
+            return;
+        }
+
+        let next_position = match self.repeat_state {
+            RepeatState::Track => self.context_position,
+            _ => self.context_position + 1,
+        };
+
+        match (self.context_tracks.get(next_position), self.repeat_state) {
+            (Some(next), _) => {
+                self.item = Some(next.clone());
+                self.context_position = next_position;
+            }
+            (None, RepeatState::Context) if !self.context_tracks.is_empty() => {
+                self.item = Some(self.context_tracks[0].clone());
+                self.context_position = 0;
+            }
+            _ => {
+                self.item = None;
+                self.is_playing = false;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SpotifyBackend for FakeSpotify {
+    async fn search(
+        &self,
+        query: &str,
+        _type: SearchType,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> ClientResult<SearchResult> {
+        let state = self.request("/search")?;
+        let query = query.to_lowercase();
+        let matches = |name: &str| name.to_lowercase().contains(&query);
+
+        match _type {
+            SearchType::Track => Ok(SearchResult::Tracks(page(
+                state.tracks.iter().filter(|track| matches(&track.name)).cloned().collect(),
+                limit,
+                offset,
+            ))),
+            SearchType::Album => Ok(SearchResult::Albums(page(
+                state.albums.iter().filter(|album| matches(&album.name)).map(simplify_album).collect(),
+                limit,
+                offset,
+            ))),
+            SearchType::Playlist => Ok(SearchResult::Playlists(page(
+                state.playlists.iter().filter(|playlist| matches(&playlist.name)).map(simplify_playlist).collect(),
+                limit,
+                offset,
+            ))),
+            SearchType::Artist => Ok(SearchResult::Artists(page(
+                state.artists.iter().filter(|artist| matches(&artist.name)).cloned().collect(),
+                limit,
+                offset,
+            ))),
+            _ => Err(api_error(400, "/search", "Unsupported search type")),
+        }
+    }
+
+    async fn track(&self, track_id: TrackId<'_>) -> ClientResult<FullTrack> {
+        self.request("/tracks")?.find_track(track_id.id())
+    }
+
+    // Like the real api only the first page of tracks is included, the rest
+    // have to be fetched separately
+    async fn album(&self, album_id: AlbumId<'_>) -> ClientResult<FullAlbum> {
+        let mut album = self.request("/albums")?
+            .albums
+            .iter()
+            .find(|album| album.id == album_id)
+            .cloned()
+            .ok_or_else(|| api_error(404, "/albums", "Non existing id"))?;
+
+        album.tracks = page(album.tracks.items, Some(50), None);
+        Ok(album)
+    }
+
+    async fn album_tracks(
+        &self,
+        album_id: AlbumId<'_>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> ClientResult<Page<SimplifiedTrack>> {
+        let state = self.request("/albums")?;
+        let album = state.albums
+            .iter()
+            .find(|album| album.id == album_id)
+            .ok_or_else(|| api_error(404, "/albums", "Non existing id"))?;
+
+        Ok(page(album.tracks.items.clone(), limit, offset))
+    }
+
+    async fn playlist(&self, playlist_id: PlaylistId<'_>) -> ClientResult<FullPlaylist> {
+        let mut playlist = self.request("/playlists")?
+            .playlists
+            .iter()
+            .find(|playlist| playlist.id == playlist_id)
+            .cloned()
+            .ok_or_else(|| api_error(404, "/playlists", "Non existing id"))?;
+
+        playlist.tracks = page(playlist.tracks.items, Some(100), None);
+        Ok(playlist)
+    }
+
+    async fn playlist_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> ClientResult<Page<PlaylistItem>> {
+        let state = self.request("/playlists")?;
+        let playlist = state.playlists
+            .iter()
+            .find(|playlist| playlist.id == playlist_id)
+            .ok_or_else(|| api_error(404, "/playlists", "Non existing id"))?;
+
+        Ok(page(playlist.tracks.items.clone(), limit, offset))
+    }
+
+    async fn artist(&self, artist_id: ArtistId<'_>) -> ClientResult<FullArtist> {
+        self.request("/artists")?
+            .artists
+            .iter()
+            .find(|artist| artist.id == artist_id)
+            .cloned()
+            .ok_or_else(|| api_error(404, "/artists", "Non existing id"))
+    }
+
+    async fn start_uris_playback(
+        &self,
+        uris: Vec<PlayableId<'_>>,
+        device_id: Option<&str>,
+    ) -> ClientResult<()> {
+        let mut state = self.request("/me/player/play")?;
+        state.active_device(device_id)?;
+
+        let tracks = uris
+            .iter()
+            .map(|uri| state.find_track(uri.id()))
+            .collect::<ClientResult<Vec<FullTrack>>>()?;
+
+        state.play_list(tracks, None)
+    }
+
+    async fn start_context_playback(
+        &self,
+        context: PlayContextId<'_>,
+        device_id: Option<&str>,
+    ) -> ClientResult<()> {
+        let mut state = self.request("/me/player/play")?;
+        state.active_device(device_id)?;
+
+        let (tracks, _type) = match &context {
+            PlayContextId::Album(id) => {
+                let album = state.albums
+                    .iter()
+                    .find(|album| &album.id == id)
+                    .ok_or_else(|| api_error(404, "/albums", "Non existing id"))?;
+                let tracks = album.tracks.items
+                    .iter()
+                    .filter_map(|track| track.id.as_ref())
+                    .map(|id| state.find_track(id.id()))
+                    .collect::<ClientResult<Vec<FullTrack>>>()?;
+                (tracks, Type::Album)
+            }
+            PlayContextId::Playlist(id) => {
+                let playlist = state.playlists
+                    .iter()
+                    .find(|playlist| &playlist.id == id)
+                    .ok_or_else(|| api_error(404, "/playlists", "Non existing id"))?;
+                let tracks = playlist.tracks.items
+                    .iter()
+                    .filter_map(|item| match &item.track {
+                        Some(PlayableItem::Track(track)) => Some(track.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                (tracks, Type::Playlist)
+            }
+            PlayContextId::Artist(id) => {
+                let artist = state.artists
+                    .iter()
+                    .find(|artist| &artist.id == id)
+                    .ok_or_else(|| api_error(404, "/artists", "Non existing id"))?;
+                let tracks = state.tracks
+                    .iter()
+                    .filter(|track| track.artists.iter().any(|track_artist| track_artist.name == artist.name))
+                    .cloned()
+                    .collect();
+                (tracks, Type::Artist)
+            }
+            PlayContextId::Show(_) => {
+                return Err(api_error(400, "/me/player/play", "Shows are not supported"));
+            }
+        };
+
+        let context = Context {
+            uri: context.uri(),
+            href: context.url(),
+            external_urls: HashMap::new(),
+            _type,
+        };
+
+        state.play_list(tracks, Some(context))
+    }
+
+    async fn add_item_to_queue(&self, item: PlayableId<'_>, device_id: Option<&str>) -> ClientResult<()> {
+        let mut state = self.request("/me/player/queue")?;
+        state.active_device(device_id)?;
+
+        let track = state.find_track(item.id())?;
+        state.queue.push_back(track);
+        Ok(())
+    }
+
+    async fn current_playback(&self) -> ClientResult<Option<CurrentPlaybackContext>> {
+        let state = self.request("/me/player")?;
+
+        let device = match state.devices.iter().find(|device| device.is_active) {
+            Some(device) => device.clone(),
+            None => return Ok(None),
+        };
+
+        Ok(Some(CurrentPlaybackContext {
+            device,
+            repeat_state: state.repeat_state,
+            shuffle_state: state.shuffle_state,
+            context: state.context.clone(),
+            timestamp: Utc::now(),
+            progress: state.item.as_ref().map(|_| state.progress),
+            is_playing: state.is_playing,
+            item: state.item.clone().map(PlayableItem::Track),
+            currently_playing_type: CurrentlyPlayingType::Track,
+            actions: Actions::default(),
+        }))
+    }
+
+    async fn current_user_queue(&self) -> ClientResult<CurrentUserQueue> {
+        let state = self.request("/me/player/queue")?;
+
+        Ok(CurrentUserQueue {
+            currently_playing: state.item.clone().map(PlayableItem::Track),
+            queue: state.queue.iter().cloned().map(PlayableItem::Track).collect(),
+        })
+    }
+
+    async fn device(&self) -> ClientResult<Vec<Device>> {
+        Ok(self.request("/me/player/devices")?.devices.clone())
+    }
+
+    async fn transfer_playback(&self, device_id: &str, play: Option<bool>) -> ClientResult<()> {
+        let mut state = self.request("/me/player")?;
+
+        if !state.devices.iter().any(|device| device.id.as_deref() == Some(device_id)) {
+            return Err(api_error(404, "/me/player", "Device not found"));
+        }
+
+        for device in state.devices.iter_mut() {
+            device.is_active = device.id.as_deref() == Some(device_id);
+        }
+
+        if let Some(play) = play {
+            state.is_playing = play && state.item.is_some();
+        }
+        Ok(())
+    }
+
+    async fn repeat(&self, repeat_state: RepeatState, device_id: Option<&str>) -> ClientResult<()> {
+        let mut state = self.request("/me/player/repeat")?;
+        state.active_device(device_id)?;
+        state.repeat_state = repeat_state;
+        Ok(())
+    }
+
+    async fn shuffle(&self, shuffle_state: bool, device_id: Option<&str>) -> ClientResult<()> {
+        let mut state = self.request("/me/player/shuffle")?;
+        state.active_device(device_id)?;
+        state.shuffle_state = shuffle_state;
+        Ok(())
+    }
+
+    async fn volume(&self, volume_percent: u8, device_id: Option<&str>) -> ClientResult<()> {
+        let mut state = self.request("/me/player/volume")?;
+        state.active_device(device_id)?.volume_percent = Some(volume_percent.into());
+        Ok(())
+    }
+
+    async fn next_track(&self, device_id: Option<&str>) -> ClientResult<()> {
+        let mut state = self.request("/me/player/next")?;
+        state.active_device(device_id)?;
+        state.advance();
+        Ok(())
+    }
+
+    async fn previous_track(&self, device_id: Option<&str>) -> ClientResult<()> {
+        let mut state = self.request("/me/player/previous")?;
+        state.active_device(device_id)?;
+        state.rewind();
+        Ok(())
+    }
+
+    async fn seek_track(&self, position_ms: u32, device_id: Option<&str>) -> ClientResult<()> {
+        let mut state = self.request("/me/player/seek")?;
+        state.active_device(device_id)?;
+
+        let duration = match &state.item {
+            Some(item) => item.duration,
+            None => return Err(api_error(404, "/me/player/seek", "Player command failed: No track to seek in")),
+        };
+        state.progress = Duration::from_millis(position_ms.into()).min(duration);
+        Ok(())
+    }
+
+    async fn pause_playback(&self, device_id: Option<&str>) -> ClientResult<()> {
+        let mut state = self.request("/me/player/pause")?;
+        state.active_device(device_id)?;
+        state.is_playing = false;
+        Ok(())
+    }
+
+    async fn resume_playback(&self, device_id: Option<&str>, position_ms: Option<u32>) -> ClientResult<()> {
+        let mut state = self.request("/me/player/play")?;
+        state.active_device(device_id)?;
+
+        if state.item.is_none() {
+            return Err(api_error(404, "/me/player/play", "Player command failed: Nothing to resume"));
+        }
+
+        if let Some(position) = position_ms {
+            state.progress = Duration::from_millis(position.into());
+        }
+        state.is_playing = true;
+        Ok(())
+    }
+}
+
+// An error shaped like spotify's own: the status code, the url of the endpoint
+// that refused, spotify's json body and for rate limits a retry-after header
+pub fn api_error(status: u16, path: &str, message: &str) -> ClientError {
+    rate_limit_error(status, path, message, None)
+}
+
+fn rate_limit_error(status: u16, path: &str, message: &str, retry_after: Option<u64>) -> ClientError {
+    let url = Url::parse(&format!("https://api.spotify.com/v1{}", path)).expect("invalid fake api path");
+
+    let mut response = http::Response::builder()
+        .status(status)
+        .url(url)
+        .header("content-type", "application/json");
+
+    if let Some(seconds) = retry_after {
+        response = response.header("retry-after", seconds.to_string());
+    }
+
+    let body = format!(r#"{{"error":{{"status":{},"message":"{}"}}}}"#, status, message);
+    let response = response.body(body).expect("invalid fake api response");
+
+    ClientError::from(HttpError::StatusCode(reqwest::Response::from(response)))
+}
+
+// Catalog entries keep every track in one page, trimmed when handed out
+fn full_page<T>(items: Vec<T>) -> Page<T> {
+    let total = items.len() as u32;
+    page(items, Some(total), None)
+}
+
+fn page<T>(items: Vec<T>, limit: Option<u32>, offset: Option<u32>) -> Page<T> {
+    let total = items.len() as u32;
+    let limit = limit.unwrap_or(20);
+    let offset = offset.unwrap_or(0);
+
+    Page {
+        href: String::new(),
+        items: items
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect(),
+        limit,
+        next: (offset + limit < total).then(|| format!("fake:offset:{}", offset + limit)),
+        offset,
+        previous: (offset > 0).then(|| format!("fake:offset:{}", offset.saturating_sub(limit))),
+        total,
+    }
+}
+
+fn simplify_artist(name: &str) -> SimplifiedArtist {
+    SimplifiedArtist {
+        external_urls: HashMap::new(),
+        href: None,
+        id: None,
+        name: name.to_string(),
+    }
+}
+
+fn simplify_album(album: &FullAlbum) -> SimplifiedAlbum {
+    SimplifiedAlbum {
+        album_group: None,
+        album_type: None,
+        artists: album.artists.clone(),
+        available_markets: vec![],
+        external_urls: album.external_urls.clone(),
+        href: Some(album.href.clone()),
+        id: Some(album.id.clone()),
+        images: album.images.clone(),
+        name: album.name.clone(),
+        release_date: Some(album.release_date.clone()),
+        release_date_precision: None,
+        restrictions: None,
+    }
+}
+
+fn simplify_track(track: &FullTrack) -> SimplifiedTrack {
+    SimplifiedTrack {
+        artists: track.artists.clone(),
+        available_markets: None,
+        disc_number: track.disc_number,
+        duration: track.duration,
+        explicit: track.explicit,
+        external_urls: track.external_urls.clone(),
+        href: track.href.clone(),
+        id: track.id.clone(),
+        is_local: track.is_local,
+        is_playable: track.is_playable,
+        linked_from: None,
+        restrictions: None,
+        name: track.name.clone(),
+        preview_url: None,
+        track_number: track.track_number,
+    }
+}
+
+fn simplify_playlist(playlist: &FullPlaylist) -> SimplifiedPlaylist {
+    SimplifiedPlaylist {
+        collaborative: playlist.collaborative,
+        external_urls: playlist.external_urls.clone(),
+        href: playlist.href.clone(),
+        id: playlist.id.clone(),
+        images: playlist.images.clone(),
+        name: playlist.name.clone(),
+        owner: playlist.owner.clone(),
+        public: playlist.public,
+        snapshot_id: playlist.snapshot_id.clone(),
+        tracks: PlaylistTracksRef {
+            href: String::new(),
+            total: playlist.tracks.total,
+        },
+    }
+}
+
+// Helpers for building catalog entries. Ids must be alphanumeric, as with
+// the real api.
+
+pub fn track(id: &str, name: &str, artist: &str, duration_secs: u64) -> FullTrack {
+    FullTrack {
+        album: SimplifiedAlbum {
+            album_group: None,
+            album_type: None,
+            artists: vec![simplify_artist(artist)],
+            available_markets: vec![],
+            external_urls: HashMap::new(),
+            href: None,
+            id: None,
+            images: vec![],
+            name: String::new(),
+            release_date: None,
+            release_date_precision: None,
+            restrictions: None,
+        },
+        artists: vec![simplify_artist(artist)],
+        available_markets: vec![],
+        disc_number: 1,
+        duration: Duration::from_secs(duration_secs),
+        explicit: false,
+        external_ids: HashMap::new(),
+        external_urls: HashMap::new(),
+        href: None,
+        id: Some(TrackId::from_id(id.to_string()).expect("invalid fake track id")),
+        is_local: false,
+        is_playable: Some(true),
+        linked_from: None,
+        restrictions: None,
+        name: name.to_string(),
+        popularity: 0,
+        preview_url: None,
+        track_number: 1,
+    }
+}
+
+pub fn album(id: &str, name: &str, artist: &str, tracks: &[FullTrack]) -> FullAlbum {
+    let items = tracks
+        .iter()
+        .enumerate()
+        .map(|(index, track)| SimplifiedTrack {
+            track_number: index as u32 + 1,
+            ..simplify_track(track)
+        })
+        .collect();
+
+    FullAlbum {
+        artists: vec![simplify_artist(artist)],
+        album_type: AlbumType::Album,
+        available_markets: None,
+        copyrights: vec![],
+        external_ids: HashMap::new(),
+        external_urls: HashMap::new(),
+        genres: vec![],
+        href: String::new(),
+        id: AlbumId::from_id(id.to_string()).expect("invalid fake album id"),
+        images: vec![],
+        name: name.to_string(),
+        popularity: 0,
+        release_date: "1987-11-12".to_string(),
+        release_date_precision: DatePrecision::Day,
+        tracks: full_page(items),
+        label: None,
+    }
+}
+
+pub fn playlist(id: &str, name: &str, owner: &str, tracks: &[FullTrack]) -> FullPlaylist {
+    let items = tracks
+        .iter()
+        .map(|track| PlaylistItem {
+            added_at: None,
+            added_by: None,
+            is_local: false,
+            track: Some(PlayableItem::Track(track.clone())),
+        })
+        .collect();
+
+    FullPlaylist {
+        collaborative: false,
+        description: None,
+        external_urls: HashMap::new(),
+        followers: Followers::default(),
+        href: String::new(),
+        id: PlaylistId::from_id(id.to_string()).expect("invalid fake playlist id"),
+        images: vec![],
+        name: name.to_string(),
+        owner: PublicUser {
+            display_name: Some(owner.to_string()),
+            external_urls: HashMap::new(),
+            followers: None,
+            href: String::new(),
+            id: UserId::from_id(owner.to_string()).expect("invalid fake user id"),
+            images: vec![],
+        },
+        public: Some(true),
+        snapshot_id: String::new(),
+        tracks: full_page(items),
+    }
+}
+
+pub fn artist(id: &str, name: &str) -> FullArtist {
+    FullArtist {
+        external_urls: HashMap::new(),
+        followers: Followers::default(),
+        genres: vec![],
+        href: String::new(),
+        id: ArtistId::from_id(id.to_string()).expect("invalid fake artist id"),
+        images: vec![],
+        name: name.to_string(),
+        popularity: 0,
+    }
+}
+
+pub fn device(id: &str, name: &str) -> Device {
+    Device {
+        id: Some(id.to_string()),
+        is_active: false,
+        is_private_session: false,
+        is_restricted: false,
+        name: name.to_string(),
+        _type: DeviceType::Speaker,
+        volume_percent: Some(50),
+    }
+}
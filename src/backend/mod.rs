@@ -0,0 +1,272 @@
+pub mod fake;
+
+use std::future::Future;
+use std::time::Duration;
+
+use log::warn;
+use serenity::async_trait;
+
+use rspotify::{
+    AuthCodeSpotify,
+    ClientError,
+    ClientResult,
+    http::HttpError,
+    clients::BaseClient,
+    clients::OAuthClient,
+    model::enums::types::AdditionalType,
+    model::enums::types::SearchType,
+    model::enums::misc::RepeatState,
+    model::search::SearchResult,
+    model::album::FullAlbum,
+    model::artist::FullArtist,
+    model::playlist::FullPlaylist,
+    model::playlist::PlaylistItem,
+    model::track::FullTrack,
+    model::track::SimplifiedTrack,
+    model::page::Page,
+    model::device::Device,
+    model::context::CurrentPlaybackContext,
+    model::context::CurrentUserQueue,
+    model::idtypes::AlbumId,
+    model::idtypes::ArtistId,
+    model::idtypes::PlaylistId,
+    model::idtypes::TrackId,
+    prelude::PlayContextId,
+    prelude::PlayableId,
+};
+
+pub use fake::FakeSpotify;
+
+// The subset of the spotify api the commands rely on. Commands are generic
+// over this so they can be run against `FakeSpotify` as well as the real
+// client.
+#[async_trait]
+pub trait SpotifyBackend: Send + Sync {
+    async fn search(
+        &self,
+        query: &str,
+        _type: SearchType,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> ClientResult<SearchResult>;
+
+    async fn track(&self, track_id: TrackId<'_>) -> ClientResult<FullTrack>;
+
+    async fn album(&self, album_id: AlbumId<'_>) -> ClientResult<FullAlbum>;
+
+    async fn album_tracks(
+        &self,
+        album_id: AlbumId<'_>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> ClientResult<Page<SimplifiedTrack>>;
+
+    async fn playlist(&self, playlist_id: PlaylistId<'_>) -> ClientResult<FullPlaylist>;
+
+    async fn playlist_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> ClientResult<Page<PlaylistItem>>;
+
+    async fn artist(&self, artist_id: ArtistId<'_>) -> ClientResult<FullArtist>;
+
+    async fn start_uris_playback(
+        &self,
+        uris: Vec<PlayableId<'_>>,
+        device_id: Option<&str>,
+    ) -> ClientResult<()>;
+
+    async fn start_context_playback(
+        &self,
+        context: PlayContextId<'_>,
+        device_id: Option<&str>,
+    ) -> ClientResult<()>;
+
+    // only used for linked accounts, the shared account queues through `LocalQueue`
+    async fn add_item_to_queue(&self, item: PlayableId<'_>, device_id: Option<&str>) -> ClientResult<()>;
+
+    async fn current_playback(&self) -> ClientResult<Option<CurrentPlaybackContext>>;
+
+    async fn current_user_queue(&self) -> ClientResult<CurrentUserQueue>;
+
+    async fn device(&self) -> ClientResult<Vec<Device>>;
+
+    async fn transfer_playback(&self, device_id: &str, play: Option<bool>) -> ClientResult<()>;
+
+    async fn repeat(&self, state: RepeatState, device_id: Option<&str>) -> ClientResult<()>;
+
+    async fn shuffle(&self, state: bool, device_id: Option<&str>) -> ClientResult<()>;
+
+    async fn volume(&self, volume_percent: u8, device_id: Option<&str>) -> ClientResult<()>;
+
+    async fn next_track(&self, device_id: Option<&str>) -> ClientResult<()>;
+
+    async fn previous_track(&self, device_id: Option<&str>) -> ClientResult<()>;
+
+    async fn seek_track(&self, position_ms: u32, device_id: Option<&str>) -> ClientResult<()>;
+
+    async fn pause_playback(&self, device_id: Option<&str>) -> ClientResult<()>;
+
+    async fn resume_playback(&self, device_id: Option<&str>, position_ms: Option<u32>) -> ClientResult<()>;
+}
+
+#[async_trait]
+impl SpotifyBackend for AuthCodeSpotify {
+    async fn search(
+        &self,
+        query: &str,
+        _type: SearchType,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> ClientResult<SearchResult> {
+        BaseClient::search(self, query, _type, None, None, limit, offset).await
+    }
+
+    async fn track(&self, track_id: TrackId<'_>) -> ClientResult<FullTrack> {
+        BaseClient::track(self, track_id).await
+    }
+
+    async fn album(&self, album_id: AlbumId<'_>) -> ClientResult<FullAlbum> {
+        BaseClient::album(self, album_id).await
+    }
+
+    async fn album_tracks(
+        &self,
+        album_id: AlbumId<'_>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> ClientResult<Page<SimplifiedTrack>> {
+        BaseClient::album_track_manual(self, album_id, limit, offset).await
+    }
+
+    async fn playlist(&self, playlist_id: PlaylistId<'_>) -> ClientResult<FullPlaylist> {
+        BaseClient::playlist(self, playlist_id, None, None).await
+    }
+
+    async fn playlist_items(
+        &self,
+        playlist_id: PlaylistId<'_>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> ClientResult<Page<PlaylistItem>> {
+        BaseClient::playlist_items_manual(self, playlist_id, None, None, limit, offset).await
+    }
+
+    async fn artist(&self, artist_id: ArtistId<'_>) -> ClientResult<FullArtist> {
+        BaseClient::artist(self, artist_id).await
+    }
+
+    async fn start_uris_playback(
+        &self,
+        uris: Vec<PlayableId<'_>>,
+        device_id: Option<&str>,
+    ) -> ClientResult<()> {
+        OAuthClient::start_uris_playback(self, uris, device_id, None, None).await
+    }
+
+    async fn start_context_playback(
+        &self,
+        context: PlayContextId<'_>,
+        device_id: Option<&str>,
+    ) -> ClientResult<()> {
+        OAuthClient::start_context_playback(self, context, device_id, None, None).await
+    }
+
+    async fn add_item_to_queue(&self, item: PlayableId<'_>, device_id: Option<&str>) -> ClientResult<()> {
+        OAuthClient::add_item_to_queue(self, item, device_id).await
+    }
+
+    async fn current_playback(&self) -> ClientResult<Option<CurrentPlaybackContext>> {
+        OAuthClient::current_playback(self, None, None::<Vec<&AdditionalType>>).await
+    }
+
+    async fn current_user_queue(&self) -> ClientResult<CurrentUserQueue> {
+        OAuthClient::current_user_queue(self).await
+    }
+
+    async fn device(&self) -> ClientResult<Vec<Device>> {
+        OAuthClient::device(self).await
+    }
+
+    async fn transfer_playback(&self, device_id: &str, play: Option<bool>) -> ClientResult<()> {
+        OAuthClient::transfer_playback(self, device_id, play).await
+    }
+
+    async fn repeat(&self, state: RepeatState, device_id: Option<&str>) -> ClientResult<()> {
+        OAuthClient::repeat(self, state, device_id).await
+    }
+
+    async fn shuffle(&self, state: bool, device_id: Option<&str>) -> ClientResult<()> {
+        OAuthClient::shuffle(self, state, device_id).await
+    }
+
+    async fn volume(&self, volume_percent: u8, device_id: Option<&str>) -> ClientResult<()> {
+        OAuthClient::volume(self, volume_percent, device_id).await
+    }
+
+    async fn next_track(&self, device_id: Option<&str>) -> ClientResult<()> {
+        OAuthClient::next_track(self, device_id).await
+    }
+
+    async fn previous_track(&self, device_id: Option<&str>) -> ClientResult<()> {
+        OAuthClient::previous_track(self, device_id).await
+    }
+
+    async fn seek_track(&self, position_ms: u32, device_id: Option<&str>) -> ClientResult<()> {
+        OAuthClient::seek_track(self, position_ms, device_id).await
+    }
+
+    async fn pause_playback(&self, device_id: Option<&str>) -> ClientResult<()> {
+        OAuthClient::pause_playback(self, device_id).await
+    }
+
+    async fn resume_playback(&self, device_id: Option<&str>, position_ms: Option<u32>) -> ClientResult<()> {
+        OAuthClient::resume_playback(self, device_id, position_ms).await
+    }
+}
+
+// attempts at a rate limited request before giving up on it
+const MAX_ATTEMPTS: u32 = 3;
+
+// How long spotify asked us to back off for, if `error` is a rate limit
+pub fn retry_after(error: &ClientError) -> Option<Duration> {
+    match error {
+        ClientError::Http(http) => match http.as_ref() {
+            HttpError::StatusCode(response) if response.status().as_u16() == 429 => {
+                let seconds = response
+                    .headers()
+                    .get("retry-after")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(1);
+                Some(Duration::from_secs(seconds))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Run `request`, waiting and trying again when spotify rate limits it
+pub async fn with_retry<T, F, Fut>(mut request: F) -> ClientResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ClientResult<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Err(why) if attempt < MAX_ATTEMPTS => match retry_after(&why) {
+                Some(wait) => {
+                    warn!("Rate limited by spotify, retrying in {}s", wait.as_secs());
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                }
+                None => return Err(why),
+            },
+            result => return result,
+        }
+    }
+}
@@ -7,23 +7,28 @@ use serenity::model::prelude::interaction::application_command::{
 };
 
 use rspotify::{
-    AuthCodeSpotify,
-    clients::OAuthClient,
     model::device::Device,
 };
 
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
 use crate::values_from_options;
 use crate::str_from_value;
 
-pub async fn run(options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
+pub async fn run<S: SpotifyBackend + ?Sized>(
+    options: &[CommandDataOption],
+    spotify: &S,
+    default_device: Option<&str>,
+) -> Result<String, CommandError> {
     let values: Vec<&CommandDataOptionValue> = values_from_options(options)?;
 
     let devices: Vec<Device> = spotify.device().await?;
 
-    let device = match str_from_value(&values, 0, None) {
-        Ok(target) => devices.iter().find(|device| device.name == target),
-        Err(_) => devices.get(0),
+    // without a name, prefer the configured default device over whichever is listed first
+    let device = match (str_from_value(&values, 0, None), default_device) {
+        (Ok(target), _) => devices.iter().find(|device| device.name == target),
+        (Err(_), Some(target)) => devices.iter().find(|device| device.name == target).or(devices.first()),
+        (Err(_), None) => devices.first(),
     };
 
     if let Some(dev) = device {
@@ -46,6 +51,7 @@ pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicatio
                 .name("name")
                 .description("Name of device to connect to")
                 .kind(CommandOptionType::String)
+                .set_autocomplete(true)
                 .required(false)
         })
 }
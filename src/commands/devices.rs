@@ -5,14 +5,13 @@ use serenity::model::prelude::interaction::application_command::{
 };
 
 use rspotify::{
-    AuthCodeSpotify,
-    clients::OAuthClient,
     model::device::Device,
 };
 
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
 
-pub async fn run(_options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
+pub async fn run<S: SpotifyBackend + ?Sized>(_options: &[CommandDataOption], spotify: &S) -> Result<String, CommandError> {
     let devices: Vec<Device> = spotify.device().await?;
 
     if devices.len() == 0 {
@@ -5,16 +5,14 @@ use serenity::model::prelude::interaction::application_command::{
 };
 
 use rspotify::{
-    AuthCodeSpotify,
-    clients::OAuthClient,
-    model::enums::types::AdditionalType,
     model::device::Device,
 };
 
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
 
-pub async fn run(_options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
-    let playback = spotify.current_playback(None, None::<Vec<&AdditionalType>>)
+pub async fn run<S: SpotifyBackend + ?Sized>(_options: &[CommandDataOption], spotify: &S) -> Result<String, CommandError> {
+    let playback = spotify.current_playback()
         .await?
         .ok_or("No current playback")?;
 
@@ -0,0 +1,21 @@
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::id::UserId;
+use serenity::model::prelude::interaction::application_command::{
+    CommandDataOption,
+};
+
+use crate::CommandError;
+use crate::accounts::Accounts;
+
+pub async fn run(_options: &[CommandDataOption], accounts: Option<&Accounts>, user: UserId) -> Result<String, CommandError> {
+    let accounts = accounts.ok_or("Account linking is not set up on this bot")?;
+
+    let url = accounts.start_link(user).await?;
+    Ok(format!("Open this link to connect your spotify account: {url}"))
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("link")
+        .description("Link your own spotify account")
+}
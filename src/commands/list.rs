@@ -1,54 +1,311 @@
-use log::warn;
-use log::info;
+use std::collections::HashSet;
+use std::time::Duration;
 
 use serenity::builder::CreateApplicationCommand;
+use serenity::builder::CreateComponents;
+use serenity::builder::CreateEmbed;
+use serenity::model::application::component::ButtonStyle;
 use serenity::model::prelude::interaction::application_command::{
     CommandDataOption,
 };
 
+use tokio::sync::Mutex;
+
 use rspotify::{
-    AuthCodeSpotify,
-    clients::OAuthClient,
+    model::enums::misc::RepeatState,
+    model::enums::types::Type,
+    model::context::Context,
+    model::context::CurrentPlaybackContext,
     model::PlayableItem,
+    prelude::Id,
 };
 
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
+use crate::local_queue::LocalQueue;
+use crate::playing_context;
+use crate::playing_context::ContextTrack;
+use crate::format_duration;
+use crate::response::Response;
+use crate::response::state_colour;
+
+// How many tracks to show from spotify's queue and from the playing context
+const MAX_SPOTIFY_QUEUE: usize = 10;
+const MAX_CONTEXT_TRACKS: u32 = 10;
+
+// entries on each page of the reply
+const PAGE_SIZE: usize = 10;
+
+const PAGE_PREFIX: &str = "list_page:";
+
+// Something that plays after the current track
+struct Upcoming {
+    description: String,
+    duration: Duration,
+}
+
+pub async fn run<S: SpotifyBackend + ?Sized>(
+    _options: &[CommandDataOption],
+    spotify: &S,
+    queue: &Mutex<LocalQueue>,
+) -> Result<Response, CommandError> {
+    page(spotify, queue, 0).await
+}
+
+// One page of what plays next, with buttons to flip to the others
+pub async fn page<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    queue: &Mutex<LocalQueue>,
+    page: usize,
+) -> Result<Response, CommandError> {
+    let playback = spotify.current_playback().await?;
+
+    let mut embed = CreateEmbed::default();
+    embed.colour(state_colour(matches!(&playback, Some(playback) if playback.is_playing)));
 
-pub async fn run(_options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
-    let queue = spotify.current_user_queue().await?;
-
-    let mut result: String = match queue.currently_playing {
-        Some(PlayableItem::Track(playable)) => format!(
-            "> Currently playing {} by {} \n> Queue: \n", 
-            playable.name, 
-            playable.artists[0].name,
-        ),
-        Some(PlayableItem::Episode(playable)) => format!(
-            "> Currently playing {} \n> Queue: \n", 
-            playable.name, 
-        ),
-        None => "> Queue: \n".to_string(),
+    // how long until the current track ends, entries are counted from there
+    let mut remaining = Duration::ZERO;
+
+    match playback.as_ref().and_then(|playback| playback.item.as_ref()) {
+        Some(PlayableItem::Track(playable)) => {
+            embed.title(format!("Currently playing {} by {}", playable.name, playable.artists[0].name));
+            remaining = playable.duration;
+        }
+        Some(PlayableItem::Episode(playable)) => {
+            embed.title(format!("Currently playing {}", playable.name));
+            remaining = playable.duration;
+        }
+        None => {
+            embed.title("Queue");
+        }
     };
 
-    for item in queue.queue {
-        match item {
-            PlayableItem::Track(track) => {
-                let artist = &track.artists[0].name;
-                let name = &track.name;
-                result.push_str(format!("> {artist} \u{2014} {name}\n").as_ref());
-            }
-            PlayableItem::Episode(episode) => {
-                let name = &episode.name;
-                result.push_str(format!("> {name}\n").as_ref());
-            }
+    if let Some(progress) = playback.as_ref().and_then(|playback| playback.progress) {
+        remaining = remaining.saturating_sub(progress);
+    }
+
+    let mut upcoming: Vec<Upcoming> = vec![];
+
+    // the bot's own queue plays first
+    {
+        let queue = queue.lock().await;
+
+        for entry in queue.entries() {
+            upcoming.push(Upcoming {
+                description: format!("{} \u{2014} {} (<@{}>)", entry.artist, entry.name, entry.requested_by),
+                duration: entry.duration,
+            });
+        }
+    }
+
+    let queued = !upcoming.is_empty();
+
+    let note = match &playback {
+        Some(playback) => spotify_upcoming(spotify, playback, queued, &mut upcoming).await?,
+        None => None,
+    };
+
+    let pages = upcoming.len().div_ceil(PAGE_SIZE).max(1);
+    let page = page.min(pages - 1);
+
+    let mut description = String::new();
+    let mut until = remaining;
+
+    for (index, entry) in upcoming.iter().enumerate() {
+        if index / PAGE_SIZE == page {
+            let position = index + 1;
+            let duration = format_duration(entry.duration);
+            let eta = if until.is_zero() {
+                "up next".to_string()
+            } else {
+                format!("in {}", format_duration(until))
+            };
+            description.push_str(&format!("{position}. {} [{duration}] \u{2014} {eta}\n", entry.description));
         }
+        until += entry.duration;
     }
 
-    Ok(result)
+    if upcoming.is_empty() && note.is_none() {
+        description.push_str("Nothing queued\n");
+    }
+
+    if let Some(note) = note {
+        if page == pages - 1 {
+            description.push_str(&format!("{note}\n"));
+        }
+    }
+
+    embed.description(description);
+
+    let total: Duration = upcoming.iter().map(|entry| entry.duration).sum();
+    let tracks = if upcoming.len() == 1 { "track" } else { "tracks" };
+    embed.footer(|footer| {
+        footer.text(format!(
+            "{} {tracks}, {} long \u{2022} page {} of {pages}",
+            upcoming.len(),
+            format_duration(total),
+            page + 1,
+        ))
+    });
+
+    let response = Response::embed(embed);
+
+    if pages > 1 {
+        Ok(response.with_components(components(page, pages)))
+    } else {
+        Ok(response)
+    }
+}
+
+// Adds the upcoming items spotify knows about, from its own queue and the
+// playing context. Returns a note on what couldn't be listed. When the bot has
+// tracks `queued` the context is left out, playing them replaces it.
+async fn spotify_upcoming<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    playback: &CurrentPlaybackContext,
+    queued: bool,
+    upcoming: &mut Vec<Upcoming>,
+) -> Result<Option<String>, CommandError> {
+    let current_id = playback.item.as_ref().and_then(item_id);
+
+    let (context_tracks, note) = match &playback.context {
+        Some(_) if playback.repeat_state == RepeatState::Track => {
+            (vec![], Some("Repeat is on, the current track plays again".to_string()))
+        }
+        Some(context) if queued => {
+            (vec![], Some(format!("The {} stops once the queued tracks start", context_name(context))))
+        }
+        Some(context) if playback.shuffle_state => {
+            (vec![], Some(format!("Shuffle is on, the rest of the {} plays in random order", context_name(context))))
+        }
+        Some(context) => match &current_id {
+            Some(current_id) => {
+                let repeat = playback.repeat_state == RepeatState::Context;
+                upcoming_context_tracks(spotify, context, current_id, repeat).await?
+            }
+            None => (vec![], None),
+        },
+        None => (vec![], None),
+    };
+
+    // spotify's queue also lists the upcoming context tracks, those are shown
+    // in context order below instead
+    let context_ids: HashSet<&str> = context_tracks.iter().filter_map(|track| track.id.as_deref()).collect();
+
+    let spotify_queue = spotify.current_user_queue().await?.queue;
+    let spotify_queue = spotify_queue
+        .iter()
+        .filter(|item| item_id(item).map(|id| !context_ids.contains(id.as_str())).unwrap_or(true))
+        .take(MAX_SPOTIFY_QUEUE);
+
+    for item in spotify_queue {
+        let (artist, name, duration) = match item {
+            PlayableItem::Track(track) => (
+                track.artists.first().map(|artist| artist.name.as_str()).unwrap_or_default(),
+                track.name.as_str(),
+                track.duration,
+            ),
+            PlayableItem::Episode(episode) => (episode.show.name.as_str(), episode.name.as_str(), episode.duration),
+        };
+        upcoming.push(Upcoming {
+            description: format!("{artist} \u{2014} {name} (spotify queue)"),
+            duration,
+        });
+    }
+
+    for track in &context_tracks {
+        upcoming.push(Upcoming {
+            description: format!("{} \u{2014} {}", track.artist, track.name),
+            duration: track.duration,
+        });
+    }
+
+    Ok(note)
+}
+
+fn components(page: usize, pages: usize) -> CreateComponents {
+    let mut components = CreateComponents::default();
+
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button
+                .custom_id(format!("{}{}", PAGE_PREFIX, page.saturating_sub(1)))
+                .label("Previous")
+                .style(ButtonStyle::Secondary)
+                .disabled(page == 0)
+        })
+        .create_button(|button| {
+            button
+                .custom_id(format!("{}{}", PAGE_PREFIX, page + 1))
+                .label("Next")
+                .style(ButtonStyle::Secondary)
+                .disabled(page + 1 >= pages)
+        })
+    });
+
+    components
+}
+
+// The page a previous or next button leads to
+pub fn parse_button(custom_id: &str) -> Option<usize> {
+    custom_id.strip_prefix(PAGE_PREFIX)?.parse().ok()
+}
+
+// Tracks after `current_id` in an album or playlist, wrapping around to the
+// start when the context repeats. Artist contexts have no order to show.
+async fn upcoming_context_tracks<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    context: &Context,
+    current_id: &str,
+    repeat: bool,
+) -> Result<(Vec<ContextTrack>, Option<String>), CommandError> {
+    if !matches!(context._type, Type::Album | Type::Playlist) {
+        let note = format!("Playing from this {}, upcoming tracks are picked by spotify", context_name(context));
+        return Ok((vec![], Some(note)));
+    }
+
+    let position = match playing_context::position(spotify, context, current_id).await? {
+        Some(position) => position,
+        None => return Ok((vec![], None)),
+    };
+
+    let (mut upcoming, total) = playing_context::tracks(spotify, context, position + 1, MAX_CONTEXT_TRACKS).await?;
+
+    let remaining = MAX_CONTEXT_TRACKS - upcoming.len() as u32;
+    if repeat && remaining > 0 {
+        // only wrap as far as the current track
+        let (mut wrapped, _) = playing_context::tracks(spotify, context, 0, remaining.min(position + 1)).await?;
+        upcoming.append(&mut wrapped);
+    }
+
+    let note = if !repeat && position + 1 + MAX_CONTEXT_TRACKS < total {
+        Some(format!("and {} more from the {}", total - position - 1 - MAX_CONTEXT_TRACKS, context_name(context)))
+    } else {
+        None
+    };
+
+    Ok((upcoming, note))
+}
+
+fn item_id(item: &PlayableItem) -> Option<String> {
+    match item {
+        PlayableItem::Track(track) => track.id.as_ref().map(|id| id.id().to_string()),
+        PlayableItem::Episode(episode) => Some(episode.id.id().to_string()),
+    }
+}
+
+fn context_name(context: &Context) -> &'static str {
+    match context._type {
+        Type::Album => "album",
+        Type::Playlist => "playlist",
+        Type::Artist => "artist",
+        Type::Show => "show",
+        _ => "context",
+    }
 }
 
 pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
     command
         .name("list")
-        .description("List current queue")
+        .description("List what plays next, from the queue and the current album or playlist")
 }
@@ -1,3 +1,6 @@
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::CommandDataOption;
+
 pub mod search;
 pub mod play;
 pub mod queue;
@@ -8,5 +11,59 @@ pub mod pause;
 pub mod resume;
 pub mod connect;
 pub mod status;
+pub mod nowplaying;
 pub mod info;
 pub mod devices;
+pub mod link;
+pub mod unlink;
+pub mod playback;
+pub mod previous;
+pub mod seek;
+
+// Commands that can take longer than discord waits for a reply, so they are
+// acknowledged first and answered once done
+pub fn is_slow(command: &str) -> bool {
+    matches!(command, "search" | "play" | "queue" | "list" | "status" | "nowplaying")
+}
+
+// The command name with its subcommand, if it has one, like "queue add"
+pub fn full_name(name: &str, options: &[CommandDataOption]) -> String {
+    match options.first() {
+        Some(option) if option.kind == CommandOptionType::SubCommand => format!("{} {}", name, option.name),
+        _ => name.to_string(),
+    }
+}
+
+// Options shaped like the ones discord sends, for running commands in tests
+#[cfg(test)]
+pub mod testing {
+    use serde::Deserialize;
+    use serenity::json::json;
+    use serenity::json::Value;
+    use serenity::model::prelude::interaction::application_command::{
+        CommandDataOption,
+        CommandDataOptionValue,
+    };
+
+    fn option(value: Value) -> CommandDataOption {
+        CommandDataOption::deserialize(value).expect("invalid test option")
+    }
+
+    pub fn string(name: &str, value: &str) -> CommandDataOption {
+        let mut string = option(json!({ "name": name, "type": 3, "value": value }));
+        string.resolved = Some(CommandDataOptionValue::String(value.to_string()));
+        string
+    }
+
+    pub fn integer(name: &str, value: i64) -> CommandDataOption {
+        let mut integer = option(json!({ "name": name, "type": 4, "value": value }));
+        integer.resolved = Some(CommandDataOptionValue::Integer(value));
+        integer
+    }
+
+    pub fn subcommand(name: &str, options: Vec<CommandDataOption>) -> CommandDataOption {
+        let mut subcommand = option(json!({ "name": name, "type": 1 }));
+        subcommand.options = options;
+        subcommand
+    }
+}
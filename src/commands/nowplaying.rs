@@ -0,0 +1,252 @@
+use serenity::builder::CreateApplicationCommand;
+use serenity::builder::CreateEmbed;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::{
+    CommandDataOption,
+};
+
+use rspotify::{
+    model::enums::types::Type,
+    model::context::Context,
+    model::context::CurrentPlaybackContext,
+    model::idtypes::ArtistId,
+    model::idtypes::AlbumId,
+    model::idtypes::PlaylistId,
+    model::PlayableItem,
+    prelude::Id,
+};
+
+use crate::CommandError;
+use crate::backend::SpotifyBackend;
+use crate::response::Response;
+use crate::response::item_embed;
+use crate::playing_context;
+
+// Context fields for the last item shown, keyed by context uri and item, so
+// a live message only looks them up again when the track changes
+pub type ContextCache = Option<(String, Vec<(String, String)>)>;
+
+pub async fn run<S: SpotifyBackend + ?Sized>(_options: &[CommandDataOption], spotify: &S) -> Result<Response, CommandError> {
+    let playback = spotify.current_playback()
+        .await?
+        .ok_or("No current playback")?;
+
+    Ok(Response::embed(embed(spotify, &playback, &mut None).await?))
+}
+
+// Whether to keep the message updated as playback goes on
+pub fn is_live(options: &[CommandDataOption]) -> bool {
+    options.iter()
+        .find(|option| option.name == "live")
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false)
+}
+
+pub async fn embed<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    playback: &CurrentPlaybackContext,
+    cache: &mut ContextCache,
+) -> Result<CreateEmbed, CommandError> {
+    let item = playback.item.as_ref().ok_or("Nothing is playing")?;
+
+    let mut embed = item_embed(item, playback.progress, playback.is_playing);
+
+    match &playback.context {
+        Some(context) => {
+            let key = format!("{} {:?}", context.uri, item.id().map(|id| id.uri()));
+
+            let fields = match cache {
+                Some((cached_key, fields)) if *cached_key == key => fields,
+                _ => {
+                    let fields = context_fields(spotify, context, item).await?;
+                    &mut cache.insert((key, fields)).1
+                }
+            };
+
+            for (name, value) in fields.iter() {
+                embed.field(name, value, true);
+            }
+        }
+        None => {
+            embed.footer(|footer| footer.text("Not playing from an album, playlist or artist"));
+        }
+    }
+
+    if let Some(volume) = playback.device.volume_percent {
+        embed.field("Volume", format!("{volume}%"), true);
+    }
+
+    Ok(embed)
+}
+
+async fn context_fields<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    context: &Context,
+    item: &PlayableItem,
+) -> Result<Vec<(String, String)>, CommandError> {
+    let mut fields: Vec<(String, String)> = vec![];
+
+    let track_id = match item {
+        PlayableItem::Track(track) => track.id.as_ref().map(|id| id.id().to_string()),
+        PlayableItem::Episode(_) => None,
+    };
+
+    let url = context_url(context);
+
+    match context._type {
+        Type::Playlist => {
+            let playlist = spotify.playlist(PlaylistId::from_uri(&context.uri)?).await?;
+
+            fields.push(("Playlist".to_string(), format!("[{}]({url})", playlist.name)));
+
+            if let Some(owner) = &playlist.owner.display_name {
+                fields.push(("Owner".to_string(), owner.to_string()));
+            }
+
+            if let Some(description) = playlist.description.as_ref().filter(|description| !description.is_empty()) {
+                fields.push(("Description".to_string(), description.to_string()));
+            }
+
+            let total = playlist.tracks.total;
+            let position = match &track_id {
+                Some(track_id) => playing_context::position(spotify, context, track_id).await?,
+                None => None,
+            };
+
+            match position {
+                Some(position) => fields.push(("Position".to_string(), format!("Track {} of {total}", position + 1))),
+                None => fields.push(("Tracks".to_string(), total.to_string())),
+            }
+        }
+        Type::Album => {
+            let album = spotify.album(AlbumId::from_uri(&context.uri)?).await?;
+
+            fields.push(("Playing from".to_string(), format!("[{}]({url})", album.name)));
+
+            let artists: Vec<&str> = album.artists.iter().map(|artist| artist.name.as_str()).collect();
+            if !artists.is_empty() {
+                fields.push(("Artist".to_string(), artists.join(", ")));
+            }
+
+            fields.push(("Released".to_string(), album.release_date.to_string()));
+
+            // track numbers start over on each disc, so count through the album
+            let total = album.tracks.total;
+            let position = match &track_id {
+                Some(track_id) => playing_context::position(spotify, context, track_id).await?,
+                None => None,
+            };
+
+            match position {
+                Some(position) => fields.push(("Position".to_string(), format!("Track {} of {total}", position + 1))),
+                None => fields.push(("Tracks".to_string(), total.to_string())),
+            }
+        }
+        Type::Artist => {
+            let artist = spotify.artist(ArtistId::from_uri(&context.uri)?).await?;
+
+            fields.push(("Playing from".to_string(), format!("[{}]({url})", artist.name)));
+
+            if !artist.genres.is_empty() {
+                fields.push(("Genres".to_string(), artist.genres.join(", ")));
+            }
+
+            fields.push(("Followers".to_string(), artist.followers.total.to_string()));
+        }
+        _type => fields.push(("Playing from".to_string(), format!("[{_type:?}]({url})"))),
+    }
+
+    Ok(fields)
+}
+
+fn context_url(context: &Context) -> String {
+    match context.external_urls.get("spotify") {
+        Some(url) => url.to_string(),
+        None => {
+            // spotify:{type}:{id} -> https://open.spotify.com/{type}/{id}
+            let path: Vec<&str> = context.uri.split(':').skip(1).collect();
+            format!("https://open.spotify.com/{}", path.join("/"))
+        }
+    }
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("nowplaying")
+        .description("Show what is playing and the album, playlist or artist it is from")
+        .create_option(|option| {
+            option
+                .name("live")
+                .description("Keep the message updated as playback goes on")
+                .kind(CommandOptionType::Boolean)
+                .required(false)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use rspotify::prelude::PlayContextId;
+
+    use super::*;
+    use crate::backend::FakeSpotify;
+
+    async fn fields_playing<S: SpotifyBackend + ?Sized>(spotify: &S) -> Vec<(String, String)> {
+        let playback = spotify.current_playback().await.unwrap().unwrap();
+        let context = playback.context.as_ref().unwrap();
+        context_fields(spotify, context, playback.item.as_ref().unwrap()).await.unwrap()
+    }
+
+    fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields.iter().find(|(field, _)| field == name).map(|(_, value)| value.as_str())
+    }
+
+    #[tokio::test]
+    async fn album_fields() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        // the second track opens the album's second disc
+        {
+            let mut state = spotify.state();
+            let track = &mut state.albums[0].tracks.items[1];
+            track.disc_number = 2;
+            track.track_number = 1;
+        }
+        spotify.next_track(None).await.unwrap();
+
+        let fields = fields_playing(&spotify).await;
+
+        assert_eq!(field(&fields, "Playing from"), Some("[Whenever You Need Somebody](https://open.spotify.com/album/6XhjNHCyCDyyGJRM5mg40G)"));
+        assert_eq!(field(&fields, "Artist"), Some("Rick Astley"));
+        assert_eq!(field(&fields, "Released"), Some("1987-11-12"));
+        assert_eq!(field(&fields, "Position"), Some("Track 2 of 2"));
+    }
+
+    #[tokio::test]
+    async fn playlist_fields() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        let playlist = PlaylistId::from_id("37i9dQZF1DXcBWIGoYBM5M").unwrap();
+        spotify.start_context_playback(PlayContextId::Playlist(playlist), None).await.unwrap();
+        spotify.next_track(None).await.unwrap();
+
+        let fields = fields_playing(&spotify).await;
+
+        assert_eq!(field(&fields, "Playlist"), Some("[Fishify Demo](https://open.spotify.com/playlist/37i9dQZF1DXcBWIGoYBM5M)"));
+        assert_eq!(field(&fields, "Owner"), Some("fishify"));
+        assert_eq!(field(&fields, "Position"), Some("Track 2 of 3"));
+    }
+
+    #[tokio::test]
+    async fn artist_fields() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        spotify.state().artists[0].genres = vec!["pop".to_string(), "dance".to_string()];
+        let artist = ArtistId::from_id("0gxyHStUsqpMadRV0Di1Qt").unwrap();
+        spotify.start_context_playback(PlayContextId::Artist(artist), None).await.unwrap();
+
+        let fields = fields_playing(&spotify).await;
+
+        assert_eq!(field(&fields, "Playing from"), Some("[Rick Astley](https://open.spotify.com/artist/0gxyHStUsqpMadRV0Di1Qt)"));
+        assert_eq!(field(&fields, "Genres"), Some("pop, dance"));
+        assert_eq!(field(&fields, "Followers"), Some("0"));
+        assert_eq!(field(&fields, "Position"), None);
+    }
+}
@@ -7,10 +7,7 @@ use serenity::model::prelude::interaction::application_command::{
 };
 
 use rspotify::{
-    AuthCodeSpotify,
     model::enums::types::SearchType,
-    clients::BaseClient,
-    clients::OAuthClient,
     model::search::SearchResult,
     model::idtypes::TrackId,
     model::idtypes::AlbumId,
@@ -21,79 +18,93 @@ use rspotify::{
 };
 
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
 use crate::values_from_options;
 use crate::search_type_from_value;
 use crate::str_from_value;
-use crate::bool_from_value;
-use crate::id_from_url;
+use crate::uri_from_link;
 
-pub async fn run(options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
+pub async fn run<S: SpotifyBackend + ?Sized>(options: &[CommandDataOption], spotify: &S) -> Result<String, CommandError> {
     let values: Vec<&CommandDataOptionValue> = values_from_options(options)?;
 
-    // there is a bug here if link is supplied but type isn't
     let search_term: &str = str_from_value(&values, 0, Some("track"))?;
 
-    let search_type: Option<SearchType> = search_type_from_value(&values, 1, None).ok(); 
+    let search_type: SearchType = search_type_from_value(&values, 1, Some(SearchType::Track))?; 
 
-    let is_link: bool = bool_from_value(&values, 2, Some(false))?;
+    // links say what they point at, so play exactly that
+    if let Some(uri) = uri_from_link(search_term, search_type) {
+        let name = name_from_uri(spotify, &uri).await?;
+        return play_uri(spotify, &uri, &name).await;
+    }
 
-    // this is a mess
-    // fix it later
-    let result = match (is_link, search_type) {
-        (true, Some(SearchType::Track)) => {
-            let id = TrackId::from_id(id_from_url(search_term)?)?;
-            spotify.start_uris_playback([PlayableId::Track(id)], None, None, None).await?;
-            return Ok("Now playing".to_string());
-        }
-        (true, Some(_type)) => {
-            let id = id_from_url(search_term)?;
-            let context = match _type {
-                SearchType::Album => PlayContextId::Album(AlbumId::from_id(id)?),
-                SearchType::Playlist => PlayContextId::Playlist(PlaylistId::from_id(id)?),
-                SearchType::Artist => PlayContextId::Artist(ArtistId::from_id(id)?),
-                _ => return Err("Unsupported context type".into()),
-            };
-            spotify.start_context_playback(context, None, None, None).await?;
-            return Ok("Now playing".to_string());
-        }
-        (true, None) => return Err("Must specify type when using link".into()),
-        (false, Some(_type)) => spotify.search(search_term, _type, None, None, Some(1), None).await?,
-        (false, None) => spotify.search(search_term, SearchType::Track, None, None, Some(1), None).await?,
-    };
+    let result = spotify.search(search_term, search_type, Some(1), None).await?;
 
     match result {
         SearchResult::Tracks(mut page) => {
             let track = page.items.remove(0);
             let id = track.id.ok_or("No track id")?;
 
-            spotify.start_uris_playback([PlayableId::Track(id)], None, None, None).await?;
+            spotify.start_uris_playback(vec![PlayableId::Track(id)], None).await?;
             Ok(format!("Now playing {} by {}", track.name, track.artists[0].name))
         }
         SearchResult::Albums(mut page) => {
             let album = page.items.remove(0);
             let id = album.id.ok_or("No album id")?;
             
-            spotify.start_context_playback(PlayContextId::Album(id), None, None, None).await?;
+            spotify.start_context_playback(PlayContextId::Album(id), None).await?;
             Ok(format!("Now playing {} by {}", album.name, album.artists[0].name))
         }
         SearchResult::Playlists(mut page) => {
             let playlist = page.items.remove(0);
             let id = playlist.id;
 
-            spotify.start_context_playback(PlayContextId::Playlist(id), None, None, None).await?;
+            spotify.start_context_playback(PlayContextId::Playlist(id), None).await?;
             Ok(format!("Now playing {}", playlist.name))
         }
         SearchResult::Artists(mut page) => {
             let artist = page.items.remove(0);
             let id = artist.id;
 
-            spotify.start_context_playback(PlayContextId::Artist(id), None, None, None).await?;
+            spotify.start_context_playback(PlayContextId::Artist(id), None).await?;
             Ok(format!("Now playing from {}", artist.name))
         }
         _ => Err(CommandError::from("Unexpected search result type")),
     }
 }
 
+// Play exactly what `uri` points at, a track or an album, playlist or artist
+pub async fn play_uri<S: SpotifyBackend + ?Sized>(spotify: &S, uri: &str, name: &str) -> Result<String, CommandError> {
+    match uri.split(':').nth(1) {
+        Some("track") => {
+            let id = TrackId::from_uri(uri)?;
+            spotify.start_uris_playback(vec![PlayableId::Track(id)], None).await?;
+        }
+        Some("album") => spotify.start_context_playback(PlayContextId::Album(AlbumId::from_uri(uri)?), None).await?,
+        Some("playlist") => spotify.start_context_playback(PlayContextId::Playlist(PlaylistId::from_uri(uri)?), None).await?,
+        Some("artist") => spotify.start_context_playback(PlayContextId::Artist(ArtistId::from_uri(uri)?), None).await?,
+        _ => return Err("Unsupported context type".into()),
+    }
+
+    Ok(format!("Now playing {}", name))
+}
+
+// What to call the item behind `uri` when saying it is playing
+async fn name_from_uri<S: SpotifyBackend + ?Sized>(spotify: &S, uri: &str) -> Result<String, CommandError> {
+    match uri.split(':').nth(1) {
+        Some("track") => {
+            let track = spotify.track(TrackId::from_uri(uri)?).await?;
+            Ok(format!("{} by {}", track.name, track.artists[0].name))
+        }
+        Some("album") => {
+            let album = spotify.album(AlbumId::from_uri(uri)?).await?;
+            Ok(format!("{} by {}", album.name, album.artists[0].name))
+        }
+        Some("playlist") => Ok(spotify.playlist(PlaylistId::from_uri(uri)?).await?.name),
+        Some("artist") => Ok(format!("from {}", spotify.artist(ArtistId::from_uri(uri)?).await?.name)),
+        _ => Err("Unsupported context type".into()),
+    }
+}
+
 pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
     command
         .name("play")
@@ -101,8 +112,9 @@ pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicatio
         .create_option(|option| {
             option
                 .name("name")
-                .description("Name of music search for")
+                .description("Name of music to search for, or a spotify link")
                 .kind(CommandOptionType::String)
+                .set_autocomplete(true)
                 .required(true)
         })
         .create_option(|option| {
@@ -116,11 +128,84 @@ pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicatio
                 .add_string_choice("artist", "artist")
                 .required(false)
         })
-        .create_option(|option| {
-            option
-                .name("link")
-                .description("Whether the search term should be interpretted as a link")
-                .kind(CommandOptionType::Boolean)
-                .required(false)
-        })
+}
+
+#[cfg(test)]
+mod tests {
+    use rspotify::model::enums::types::Type;
+
+    use super::*;
+    use crate::backend::FakeSpotify;
+    use crate::commands::testing::string;
+
+    fn playing(spotify: &FakeSpotify) -> Option<String> {
+        spotify.state().item.as_ref().map(|track| track.name.clone())
+    }
+
+    #[tokio::test]
+    async fn plays_the_first_search_result() {
+        let spotify = FakeSpotify::with_demo_catalog();
+
+        let reply = run(&[string("name", "together")], &spotify).await.unwrap();
+
+        assert_eq!(reply, "Now playing Together Forever by Rick Astley");
+        assert_eq!(playing(&spotify).as_deref(), Some("Together Forever"));
+        assert!(spotify.state().is_playing);
+    }
+
+    #[tokio::test]
+    async fn plays_an_album_from_a_link() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        let link = "https://open.spotify.com/album/6XhjNHCyCDyyGJRM5mg40G?si=abc123";
+
+        let reply = run(&[string("name", link)], &spotify).await.unwrap();
+
+        assert_eq!(reply, "Now playing Whenever You Need Somebody by Rick Astley");
+        assert_eq!(playing(&spotify).as_deref(), Some("Never Gonna Give You Up"));
+        let context = spotify.state().context.clone().unwrap();
+        assert_eq!(context._type, Type::Album);
+        assert_eq!(context.uri, "spotify:album:6XhjNHCyCDyyGJRM5mg40G");
+    }
+
+    #[tokio::test]
+    async fn plays_a_playlist_by_name() {
+        let spotify = FakeSpotify::with_demo_catalog();
+
+        let reply = run(&[string("name", "demo"), string("type", "playlist")], &spotify).await.unwrap();
+
+        assert_eq!(reply, "Now playing Fishify Demo");
+        assert_eq!(spotify.state().context_tracks.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn needs_an_active_device() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        spotify.state().devices[0].is_active = false;
+
+        let result = run(&[string("name", "blue")], &spotify).await;
+
+        assert!(matches!(result, Err(CommandError::NoActiveDevice(_))));
+        assert_eq!(playing(&spotify), None);
+    }
+
+    #[tokio::test]
+    async fn unknown_links_are_not_found() {
+        let spotify = FakeSpotify::with_demo_catalog();
+
+        let result = run(&[string("name", "spotify:track:0000000000000000000000")], &spotify).await;
+
+        assert!(matches!(result, Err(CommandError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn reports_rate_limits() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        spotify.state().retry_after = 7;
+        spotify.state().failures.push_back(429);
+
+        match run(&[string("name", "blue")], &spotify).await {
+            Err(CommandError::RateLimited(wait, _)) => assert_eq!(wait.as_secs(), 7),
+            other => panic!("expected a rate limit, got {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,38 @@
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::prelude::interaction::application_command::{
+    CommandDataOption,
+};
+
+use crate::CommandError;
+use crate::backend::SpotifyBackend;
+
+pub async fn run<S: SpotifyBackend + ?Sized>(_options: &[CommandDataOption], spotify: &S) -> Result<String, CommandError> {
+    toggle(spotify).await
+}
+
+// Pause if something is playing, otherwise resume
+pub async fn toggle<S: SpotifyBackend + ?Sized>(spotify: &S) -> Result<String, CommandError> {
+    let playback = spotify.current_playback().await?;
+
+    if matches!(playback, Some(playback) if playback.is_playing) {
+        pause(spotify).await
+    } else {
+        resume(spotify).await
+    }
+}
+
+pub async fn pause<S: SpotifyBackend + ?Sized>(spotify: &S) -> Result<String, CommandError> {
+    spotify.pause_playback(None).await?;
+    Ok("Playback paused".to_string())
+}
+
+pub async fn resume<S: SpotifyBackend + ?Sized>(spotify: &S) -> Result<String, CommandError> {
+    spotify.resume_playback(None, None).await?;
+    Ok("Playback resumed".to_string())
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("playback")
+        .description("Pause playback, or resume it if paused")
+}
@@ -0,0 +1,18 @@
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::prelude::interaction::application_command::{
+    CommandDataOption,
+};
+
+use crate::CommandError;
+use crate::backend::SpotifyBackend;
+
+pub async fn run<S: SpotifyBackend + ?Sized>(_options: &[CommandDataOption], spotify: &S) -> Result<String, CommandError> {
+    spotify.previous_track(None).await?;
+    Ok("Went back a track".to_string())
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("previous")
+        .description("Go back to the previous track")
+}
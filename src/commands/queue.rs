@@ -6,84 +6,576 @@ use serenity::model::prelude::interaction::application_command::{
     CommandDataOptionValue,
 };
 
+use serenity::model::id::UserId;
+use tokio::sync::Mutex;
+use tokio::sync::watch;
+
 use rspotify::{
-    AuthCodeSpotify,
     model::enums::types::SearchType,
-    clients::BaseClient,
-    clients::OAuthClient,
     model::search::SearchResult,
+    model::idtypes::AlbumId,
+    model::idtypes::PlaylistId,
+    model::idtypes::TrackId,
+    model::PlayableItem,
+    prelude::Id,
     prelude::PlayableId,
 };
 
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
+use crate::backend::with_retry;
+use crate::local_queue::LocalQueue;
+use crate::local_queue::QueueEntry;
+use crate::local_queue::play_next;
+use crate::local_queue::is_idle;
 use crate::values_from_options;
 use crate::search_type_from_value;
 use crate::str_from_value;
+use crate::int_from_value;
+use crate::uri_from_link;
+
+pub async fn run<S: SpotifyBackend + ?Sized>(
+    options: &[CommandDataOption],
+    spotify: &S,
+    queue: Option<&Mutex<LocalQueue>>,
+    user: UserId,
+    max_tracks: u32,
+    progress: Option<&Progress>,
+    may_manage: bool,
+) -> Result<String, CommandError> {
+    let subcommand = options.first().ok_or("No subcommand")?;
+
+    match (subcommand.name.as_str(), queue) {
+        ("add", _) => add(&subcommand.options, spotify, queue, user, max_tracks, progress).await,
+        (_, Some(queue)) => manage(subcommand, queue, user, may_manage).await,
+        (_, None) => Err(CommandError::from("Linked accounts use spotify's own queue, which can't be changed")),
+    }
+}
 
-pub async fn run(options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
+async fn add<S: SpotifyBackend + ?Sized>(
+    options: &[CommandDataOption],
+    spotify: &S,
+    queue: Option<&Mutex<LocalQueue>>,
+    user: UserId,
+    max_tracks: u32,
+    progress: Option<&Progress>,
+) -> Result<String, CommandError> {
     let values: Vec<&CommandDataOptionValue> = values_from_options(options)?;
 
     let search_term: &str = str_from_value(&values, 0, Some("track"))?;
 
     let search_type: SearchType = search_type_from_value(&values, 1, Some(SearchType::Track))?; 
 
-    let result = spotify.search(search_term, search_type, None, None, Some(1), None).await?;
+    if let Some(uri) = uri_from_link(search_term, search_type) {
+        return queue_uri(spotify, queue, &uri, user, max_tracks, progress).await;
+    }
+
+    let result = spotify.search(search_term, search_type, Some(1), None).await?;
+
+    let uri = match result {
+        SearchResult::Tracks(page) => page.items.first().and_then(|track| track.id.as_ref()).map(|id| id.uri()),
+        SearchResult::Albums(page) => page.items.first().and_then(|album| album.id.as_ref()).map(|id| id.uri()),
+        SearchResult::Playlists(page) => page.items.first().map(|playlist| playlist.id.uri()),
+        _ => return Err(CommandError::from("Unexpected search result type")),
+    };
+
+    queue_uri(spotify, queue, &uri.ok_or("No results")?, user, max_tracks, progress).await
+}
+
+// Change the bot's own queue. Positions count from 1, as /list shows them.
+// Without `may_manage` users can only remove what they queued themselves.
+async fn manage(
+    subcommand: &CommandDataOption,
+    queue: &Mutex<LocalQueue>,
+    user: UserId,
+    may_manage: bool,
+) -> Result<String, CommandError> {
+    let values: Vec<&CommandDataOptionValue> = values_from_options(&subcommand.options)?;
+
+    let mut queue = queue.lock().await;
 
-    match result {
-        SearchResult::Tracks(mut page) => {
-            let track = page.items.remove(0);
-            spotify.add_item_to_queue(PlayableId::Track(track.id.ok_or("No track id")?), None).await?;
-            Ok(format!("Successfully queued {} {}", <SearchType as Into<&'static str>>::into(search_type), track.name))
+    match subcommand.name.as_str() {
+        "remove" => {
+            let position = int_from_value(&values, 0, None)?;
+            let index = index_from_position(position)?;
+
+            let entry = queue.get(index).ok_or_else(|| nothing_at(position))?;
+            if !may_manage && entry.requested_by != user {
+                return Err(CommandError::from("You can only remove tracks you queued"));
+            }
+
+            let entry = queue.remove(index).ok_or_else(|| nothing_at(position))?;
+            Ok(format!("Removed {} \u{2014} {} from the queue", entry.artist, entry.name))
         }
-        SearchResult::Albums(mut page) => {
-            let album = page.items.remove(0);
-            let tracks = spotify
-                .album(album.id.ok_or("No track id")?).await?
-                .tracks
-                .items;
-
-            for track in tracks {
-                spotify.add_item_to_queue(PlayableId::Track(track.id.ok_or("No track id")?), None).await?;
+        "move" => {
+            let from = int_from_value(&values, 0, None)?;
+            let to = int_from_value(&values, 1, None)?;
+
+            if !queue.move_entry(index_from_position(from)?, index_from_position(to)?) {
+                return Err(CommandError::SimpleError(format!("The queue only has {} tracks", queue.entries().len())));
             }
-            Ok(format!("Successfully queued {} {}", <SearchType as Into<&'static str>>::into(search_type), album.name))
+            Ok(format!("Moved track {from} to position {to}"))
+        }
+        "clear" => {
+            let count = queue.clear();
+            Ok(format!("Cleared {count} tracks from the queue"))
+        }
+        "shuffle" => {
+            queue.shuffle();
+            Ok(format!("Shuffled {} queued tracks", queue.entries().len()))
         }
-        SearchResult::Playlists(mut page) => {
-            let playlist = page.items.remove(0);
-            let items = spotify
-                .playlist(playlist.id, None, None).await?
-                .tracks
-                .items;
-
-            for item in items {
-                spotify.add_item_to_queue(
-                    item.track.ok_or("No playable track")?.id().ok_or("No track id")?, None
-                    ).await?;
+        "dedupe" => {
+            let count = queue.dedupe();
+            Ok(format!("Removed {count} duplicate tracks from the queue"))
+        }
+        _ => Err(CommandError::from("Unknown subcommand")),
+    }
+}
+
+fn index_from_position(position: i64) -> Result<usize, CommandError> {
+    match usize::try_from(position) {
+        Ok(position) if position >= 1 => Ok(position - 1),
+        _ => Err(CommandError::from("Positions start at 1")),
+    }
+}
+
+fn nothing_at(position: i64) -> CommandError {
+    CommandError::SimpleError(format!("Nothing is queued at position {position}"))
+}
+
+// Queue the track, or the tracks of the album or playlist, behind `uri`. At
+// most `max_tracks` are taken from an album or playlist. Progress on long ones
+// is sent to `progress`.
+pub async fn queue_uri<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    queue: Option<&Mutex<LocalQueue>>,
+    uri: &str,
+    user: UserId,
+    max_tracks: u32,
+    progress: Option<&Progress>,
+) -> Result<String, CommandError> {
+    let collected = match uri.split(':').nth(1) {
+        Some("track") => {
+            let track = spotify.track(TrackId::from_uri(uri)?).await?;
+            let entry = QueueEntry::from_track(&track, user).ok_or("No track id")?;
+            Collected { kind: "track", name: track.name, total: 1, entries: vec![entry], skipped: 0 }
+        }
+        Some("album") => album_entries(spotify, AlbumId::from_uri(uri)?, user, max_tracks, progress).await?,
+        Some("playlist") => playlist_entries(spotify, PlaylistId::from_uri(uri)?, user, max_tracks, progress).await?,
+        _ => return Err(CommandError::from("Only tracks, albums and playlists can be queued")),
+    };
+
+    let Collected { kind, name, total, entries, skipped } = collected;
+
+    if entries.is_empty() {
+        return Err(CommandError::SimpleError(format!("Nothing in {} {} can be queued", kind, name)));
+    }
+
+    let count = entries.len();
+    let mut failed = 0;
+
+    match queue {
+        Some(queue) => {
+            {
+                let mut queue = queue.lock().await;
+                for entry in entries {
+                    queue.push(entry);
+                }
+            }
+
+            // nothing to wait for, so start the first entry now
+            if is_idle(spotify).await? {
+                play_next(queue, spotify).await?;
             }
-            Ok(format!("Successfully queued {} {}", <SearchType as Into<&'static str>>::into(search_type), playlist.name))
         }
-        _ => Err(CommandError::from("Unexpected search result type")),
+        // linked accounts play on their owner's device, so use its own queue
+        None => {
+            for (index, entry) in entries.into_iter().enumerate() {
+                let added = with_retry(|| spotify.add_item_to_queue(PlayableId::Track(entry.id.clone()), None)).await;
+
+                // keep going, one bad track shouldn't lose the rest
+                if let Err(why) = added {
+                    warn!("Failed to queue {}: {}", entry.name, why);
+                    failed += 1;
+                }
+
+                if (index + 1) % PROGRESS_EVERY == 0 {
+                    report(progress, format!("Queueing {} {}: {}/{}", kind, name, index + 1, count));
+                }
+            }
+        }
+    }
+
+    if kind == "track" {
+        return match failed {
+            0 => Ok(format!("Successfully queued track {}", name)),
+            _ => Err(CommandError::SimpleError(format!("Failed to queue track {}", name))),
+        };
+    }
+
+    let mut reply = format!("Successfully queued {} {} ({} tracks)", kind, name, count - failed);
+
+    if skipped > 0 {
+        reply.push_str(&format!(", skipped {} local or unavailable", skipped));
+    }
+    if failed > 0 {
+        reply.push_str(&format!(", {} failed", failed));
     }
+    if count + skipped < total as usize {
+        reply.push_str(&format!(", stopped at the limit of {} of its {} tracks", max_tracks, total));
+    }
+
+    Ok(reply)
+}
+
+// Where long queueing jobs say how far along they are
+pub type Progress = watch::Sender<String>;
+
+// how often to report progress while adding tracks one by one
+const PROGRESS_EVERY: usize = 10;
+
+const ALBUM_PAGE_SIZE: u32 = 50;
+const PLAYLIST_PAGE_SIZE: u32 = 100;
+
+fn report(progress: Option<&Progress>, message: String) {
+    if let Some(progress) = progress {
+        progress.send_replace(message);
+    }
+}
+
+// What to queue from a uri
+struct Collected {
+    kind: &'static str,
+    name: String,
+    total: u32,
+    entries: Vec<QueueEntry>,
+    // local files, and tracks that can't be played here
+    skipped: usize,
+}
+
+async fn album_entries<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    id: AlbumId<'_>,
+    user: UserId,
+    max_tracks: u32,
+    progress: Option<&Progress>,
+) -> Result<Collected, CommandError> {
+    let album = with_retry(|| spotify.album(id.clone())).await?;
+    let total = album.tracks.total;
+
+    let mut entries: Vec<QueueEntry> = vec![];
+    let mut skipped = 0;
+    let mut page = album.tracks.items;
+    let mut offset = 0;
+
+    loop {
+        offset += page.len() as u32;
+
+        for track in &page {
+            if entries.len() >= max_tracks as usize {
+                break;
+            }
+            match track.is_playable {
+                Some(false) => skipped += 1,
+                _ => match QueueEntry::from_simplified(track, user) {
+                    Some(entry) => entries.push(entry),
+                    None => skipped += 1,
+                },
+            }
+        }
+
+        if page.is_empty() || offset >= total || entries.len() >= max_tracks as usize {
+            break;
+        }
+
+        report(progress, format!("Fetching album {}: {}/{} tracks", album.name, offset, total));
+
+        page = with_retry(|| spotify.album_tracks(id.clone(), Some(ALBUM_PAGE_SIZE), Some(offset))).await?.items;
+    }
+
+    Ok(Collected { kind: "album", name: album.name, total, entries, skipped })
+}
+
+async fn playlist_entries<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    id: PlaylistId<'_>,
+    user: UserId,
+    max_tracks: u32,
+    progress: Option<&Progress>,
+) -> Result<Collected, CommandError> {
+    let playlist = with_retry(|| spotify.playlist(id.clone())).await?;
+    let total = playlist.tracks.total;
+
+    let mut entries: Vec<QueueEntry> = vec![];
+    let mut skipped = 0;
+    let mut page = playlist.tracks.items;
+    let mut offset = 0;
+
+    loop {
+        offset += page.len() as u32;
+
+        for item in &page {
+            if entries.len() >= max_tracks as usize {
+                break;
+            }
+            let entry = match &item.track {
+                Some(PlayableItem::Track(track)) if !item.is_local && track.is_playable != Some(false) => {
+                    QueueEntry::from_track(track, user)
+                }
+                // episodes, local files and removed tracks
+                _ => None,
+            };
+            match entry {
+                Some(entry) => entries.push(entry),
+                None => skipped += 1,
+            }
+        }
+
+        if page.is_empty() || offset >= total || entries.len() >= max_tracks as usize {
+            break;
+        }
+
+        report(progress, format!("Fetching playlist {}: {}/{} tracks", playlist.name, offset, total));
+
+        page = with_retry(|| spotify.playlist_items(id.clone(), Some(PLAYLIST_PAGE_SIZE), Some(offset))).await?.items;
+    }
+
+    Ok(Collected { kind: "playlist", name: playlist.name, total, entries, skipped })
 }
 
 pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
     command
         .name("queue")
-        .description("Queue spotify music")
+        .description("Queue spotify music, or change what is queued")
+        .create_option(|option| {
+            option
+                .name("add")
+                .description("Queue spotify music")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("name")
+                        .description("name of music to add to queue, or a spotify link")
+                        .kind(CommandOptionType::String)
+                        .set_autocomplete(true)
+                        .required(true)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("type")
+                        .description("track, album, or playlist")
+                        .kind(CommandOptionType::String)
+                        .add_string_choice("track", "track")
+                        .add_string_choice("album", "album")
+                        .add_string_choice("playlist", "playlist")
+                        .required(false)
+                })
+        })
+        .create_option(|option| {
+            option
+                .name("remove")
+                .description("Remove a track from the queue")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("position")
+                        .description("position in /list")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(1)
+                        .required(true)
+                })
+        })
         .create_option(|option| {
             option
-                .name("name")
-                .description("name of music to add to queue")
-                .kind(CommandOptionType::String)
-                .required(true)
+                .name("move")
+                .description("Move a queued track")
+                .kind(CommandOptionType::SubCommand)
+                .create_sub_option(|option| {
+                    option
+                        .name("from")
+                        .description("position in /list")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(1)
+                        .required(true)
+                })
+                .create_sub_option(|option| {
+                    option
+                        .name("to")
+                        .description("position to move it to")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(1)
+                        .required(true)
+                })
         })
         .create_option(|option| {
             option
-                .name("type")
-                .description("track, album, or playlist")
-                .kind(CommandOptionType::String)
-                .add_string_choice("track", "track")
-                .add_string_choice("album", "album")
-                .add_string_choice("playlist", "playlist")
-                .required(false)
+                .name("clear")
+                .description("Remove everything from the queue")
+                .kind(CommandOptionType::SubCommand)
         })
+        .create_option(|option| {
+            option
+                .name("shuffle")
+                .description("Shuffle the queue")
+                .kind(CommandOptionType::SubCommand)
+        })
+        .create_option(|option| {
+            option
+                .name("dedupe")
+                .description("Remove tracks that are queued more than once")
+                .kind(CommandOptionType::SubCommand)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::backend::FakeSpotify;
+    use crate::commands::testing::{integer, string, subcommand};
+
+    const ALICE: UserId = UserId(1);
+    const BOB: UserId = UserId(2);
+
+    async fn add_as(
+        spotify: &FakeSpotify,
+        queue: Option<&Mutex<LocalQueue>>,
+        user: UserId,
+        name: &str,
+        max_tracks: u32,
+    ) -> Result<String, CommandError> {
+        let options = [subcommand("add", vec![string("name", name)])];
+        run(&options, spotify, queue, user, max_tracks, None, false).await
+    }
+
+    async fn queued_names(queue: &Mutex<LocalQueue>) -> Vec<String> {
+        queue.lock().await.entries().iter().map(|entry| entry.name.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn starts_the_first_track_when_idle() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        let queue = Mutex::new(LocalQueue::new());
+
+        let reply = add_as(&spotify, Some(&queue), ALICE, "together", 500).await.unwrap();
+        assert_eq!(reply, "Successfully queued track Together Forever");
+        assert_eq!(spotify.state().item.as_ref().unwrap().name, "Together Forever");
+        assert!(queued_names(&queue).await.is_empty());
+
+        add_as(&spotify, Some(&queue), ALICE, "blue", 500).await.unwrap();
+        assert_eq!(queued_names(&queue).await, ["Blue (Da Ba Dee)"]);
+        assert_eq!(spotify.state().item.as_ref().unwrap().name, "Together Forever");
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_paused_track() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        spotify.state().is_playing = false;
+        spotify.state().progress = Duration::from_secs(42);
+        let queue = Mutex::new(LocalQueue::new());
+
+        add_as(&spotify, Some(&queue), ALICE, "blue", 500).await.unwrap();
+
+        assert_eq!(queued_names(&queue).await, ["Blue (Da Ba Dee)"]);
+        assert_eq!(spotify.state().item.as_ref().unwrap().name, "Never Gonna Give You Up");
+        assert_eq!(spotify.state().progress, Duration::from_secs(42));
+        assert!(!spotify.state().is_playing);
+    }
+
+    #[tokio::test]
+    async fn queues_every_track_of_a_playlist() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        let queue = Mutex::new(LocalQueue::new());
+
+        let reply = add_as(&spotify, Some(&queue), ALICE, "spotify:playlist:37i9dQZF1DXcBWIGoYBM5M", 500).await.unwrap();
+
+        assert_eq!(reply, "Successfully queued playlist Fishify Demo (3 tracks)");
+        assert_eq!(spotify.state().item.as_ref().unwrap().name, "Never Gonna Give You Up");
+        assert_eq!(queued_names(&queue).await, ["Together Forever", "Blue (Da Ba Dee)"]);
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_track_limit() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        let queue = Mutex::new(LocalQueue::new());
+
+        let reply = add_as(&spotify, Some(&queue), ALICE, "spotify:playlist:37i9dQZF1DXcBWIGoYBM5M", 2).await.unwrap();
+        assert_eq!(
+            reply,
+            "Successfully queued playlist Fishify Demo (2 tracks), stopped at the limit of 2 of its 3 tracks",
+        );
+    }
+
+    #[tokio::test]
+    async fn linked_accounts_use_spotifys_queue_and_retry_rate_limits() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        spotify.state().retry_after = 0;
+
+        // the album lookup is rate limited once, then goes through
+        spotify.state().failures.push_back(429);
+        let reply = add_as(&spotify, None, ALICE, "spotify:album:6XhjNHCyCDyyGJRM5mg40G", 500).await.unwrap();
+
+        assert_eq!(reply, "Successfully queued album Whenever You Need Somebody (2 tracks)");
+        let queued: Vec<String> = spotify.state().queue.iter().map(|track| track.name.clone()).collect();
+        assert_eq!(queued, ["Never Gonna Give You Up", "Together Forever"]);
+    }
+
+    #[tokio::test]
+    async fn linked_accounts_cannot_manage_the_queue() {
+        let spotify = FakeSpotify::with_demo_catalog();
+
+        let options = [subcommand("clear", vec![])];
+        let result = run(&options, &spotify, None, ALICE, 500, None, true).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn only_managers_remove_what_others_queued() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        let queue = Mutex::new(LocalQueue::new());
+
+        // the first starts playing, the rest wait
+        add_as(&spotify, Some(&queue), ALICE, "never gonna", 500).await.unwrap();
+        add_as(&spotify, Some(&queue), ALICE, "together", 500).await.unwrap();
+        add_as(&spotify, Some(&queue), BOB, "blue", 500).await.unwrap();
+
+        let remove = |position| [subcommand("remove", vec![integer("position", position)])];
+
+        let result = run(&remove(1), &spotify, Some(&queue), BOB, 500, None, false).await;
+        assert!(result.is_err());
+
+        let reply = run(&remove(2), &spotify, Some(&queue), BOB, 500, None, false).await.unwrap();
+        assert_eq!(reply, "Removed Eiffel 65 \u{2014} Blue (Da Ba Dee) from the queue");
+
+        run(&remove(1), &spotify, Some(&queue), BOB, 500, None, true).await.unwrap();
+        assert!(queued_names(&queue).await.is_empty());
+
+        let result = run(&remove(1), &spotify, Some(&queue), BOB, 500, None, true).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn moves_and_dedupes_by_position() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        let queue = Mutex::new(LocalQueue::new());
+
+        add_as(&spotify, Some(&queue), ALICE, "never gonna", 500).await.unwrap();
+        for name in ["together", "blue", "together"] {
+            add_as(&spotify, Some(&queue), ALICE, name, 500).await.unwrap();
+        }
+
+        let options = [subcommand("move", vec![integer("from", 2), integer("to", 1)])];
+        run(&options, &spotify, Some(&queue), ALICE, 500, None, true).await.unwrap();
+        assert_eq!(queued_names(&queue).await, ["Blue (Da Ba Dee)", "Together Forever", "Together Forever"]);
+
+        let options = [subcommand("move", vec![integer("from", 4), integer("to", 1)])];
+        assert!(run(&options, &spotify, Some(&queue), ALICE, 500, None, true).await.is_err());
+
+        let options = [subcommand("dedupe", vec![])];
+        let reply = run(&options, &spotify, Some(&queue), ALICE, 500, None, true).await.unwrap();
+        assert_eq!(reply, "Removed 1 duplicate tracks from the queue");
+        assert_eq!(queued_names(&queue).await, ["Blue (Da Ba Dee)", "Together Forever"]);
+    }
 }
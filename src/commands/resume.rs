@@ -4,16 +4,12 @@ use serenity::model::prelude::interaction::application_command::{
     CommandDataOption,
 };
 
-use rspotify::{
-    AuthCodeSpotify,
-    clients::OAuthClient,
-};
-
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
+use crate::commands::playback;
 
-pub async fn run(_options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
-    spotify.resume_playback(None, None).await?;
-    Ok("Playback resumed".to_string())
+pub async fn run<S: SpotifyBackend + ?Sized>(_options: &[CommandDataOption], spotify: &S) -> Result<String, CommandError> {
+    playback::resume(spotify).await
 }
 
 pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
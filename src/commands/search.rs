@@ -1,6 +1,10 @@
+use std::collections::HashSet;
+
 use log::warn;
 
 use serenity::builder::CreateApplicationCommand;
+use serenity::builder::CreateComponents;
+use serenity::model::application::component::{ActionRow, ActionRowComponent, ButtonStyle};
 use serenity::model::prelude::command::CommandOptionType;
 use serenity::model::prelude::interaction::application_command::{
     CommandDataOption,
@@ -8,74 +12,195 @@ use serenity::model::prelude::interaction::application_command::{
 };
 
 use rspotify::{
-    AuthCodeSpotify,
     model::enums::types::SearchType,
-    clients::BaseClient,
     model::search::SearchResult,
+    prelude::Id,
 };
 
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
+use crate::response::Response;
+use crate::response::truncate;
 use crate::values_from_options;
 use crate::search_type_from_value;
 use crate::str_from_value;
 
-pub async fn run(options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
+pub const SELECT_ID: &str = "search_select";
+const PLAY_PREFIX: &str = "search_play:";
+const QUEUE_PREFIX: &str = "search_queue:";
+
+// Discord's limit on select option labels and descriptions
+const MAX_OPTION_LENGTH: usize = 100;
+
+// A search result offered in the select menu
+#[derive(Debug, Clone)]
+pub struct Choice {
+    pub label: String,
+    pub description: Option<String>,
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Play,
+    Queue,
+}
+
+pub async fn run<S: SpotifyBackend + ?Sized>(
+    options: &[CommandDataOption],
+    spotify: &S,
+    limit: u32,
+) -> Result<Response, CommandError> {
     let values: Vec<&CommandDataOptionValue> = values_from_options(options)?;
 
     let search_term: &str = str_from_value(&values, 0, Some("track"))?;
 
     let search_type: SearchType = search_type_from_value(&values, 1, Some(SearchType::Track))?; 
 
-    let result = spotify.search(search_term, search_type, None, None, Some(5), None).await?;
+    let result = spotify.search(search_term, search_type, Some(limit), None).await?;
 
     let mut result_string: String = format!("Search results for '{}'\n", search_term);
 
-    match result {
-        SearchResult::Tracks(page) => {
-            let items = page.items;
+    let choices: Vec<Choice> = match result {
+        SearchResult::Tracks(page) => page.items
+            .into_iter()
+            .filter_map(|item| Some(Choice {
+                // local files and some podcast items come without artists
+                description: Some(match item.artists.first() {
+                    Some(artist) => format!("{} \u{2014} {}", artist.name, item.album.name),
+                    None => item.album.name.clone(),
+                }),
+                uri: item.id?.uri(),
+                label: item.name,
+            }))
+            .collect(),
+        SearchResult::Albums(page) => page.items
+            .into_iter()
+            .filter_map(|item| Some(Choice {
+                description: item.artists.first().map(|artist| artist.name.clone()),
+                uri: item.id?.uri(),
+                label: item.name,
+            }))
+            .collect(),
+        SearchResult::Playlists(page) => page.items
+            .into_iter()
+            .map(|item| Choice {
+                description: item.owner.display_name,
+                uri: item.id.uri(),
+                label: item.name,
+            })
+            .collect(),
+        SearchResult::Artists(page) => page.items
+            .into_iter()
+            .map(|item| Choice {
+                description: None,
+                uri: item.id.uri(),
+                label: item.name,
+            })
+            .collect(),
+        _ => return Err(CommandError::from("Unexpected search result type")),
+    };
 
-            for item in items {
-                result_string.push_str(format!("{} \u{2014} {} \n", item.artists[0].name, item.name).as_str());
-            }
-            Ok(result_string)
-        }
-        SearchResult::Albums(page) => {
-            let items = page.items;
+    // select menu values have to be unique, and searches can repeat results
+    let mut seen = HashSet::new();
+    let choices: Vec<Choice> = choices.into_iter().filter(|choice| seen.insert(choice.uri.clone())).collect();
 
-            for item in items {
-                result_string.push_str(format!("{} \u{2014} {} \n", item.artists[0].name, item.name).as_str());
-            }
-            Ok(result_string)
-        }
-        SearchResult::Playlists(page) => {
-            let items = page.items;
+    if choices.is_empty() {
+        return Err(CommandError::from("No results"));
+    }
 
-            for item in items {
-                result_string.push_str(format!("{} \n", item.name).as_str());
-            }
-            Ok(result_string)
+    for (index, choice) in choices.iter().enumerate() {
+        let position = index + 1;
+        match &choice.description {
+            Some(description) => result_string.push_str(format!("{position}. {} \u{2014} {description}\n", choice.label).as_str()),
+            None => result_string.push_str(format!("{position}. {}\n", choice.label).as_str()),
         }
-        SearchResult::Artists(page) => {
-            let items = page.items;
+    }
 
-            for item in items {
-                result_string.push_str(format!("{} \n", item.name).as_str());
-            }
-            Ok(result_string)
-        }
-        _ => Err(CommandError::from("Unexpected search result type")),
+    Ok(Response::text(result_string).with_components(components(&choices, 0)))
+}
+
+// The select menu of results, with play and queue buttons for the selected one
+pub fn components(choices: &[Choice], selected: usize) -> CreateComponents {
+    let mut components = CreateComponents::default();
+
+    components.create_action_row(|row| {
+        row.create_select_menu(|menu| {
+            menu.custom_id(SELECT_ID).options(|options| {
+                for (index, choice) in choices.iter().enumerate() {
+                    options.create_option(|option| {
+                        option
+                            .label(truncate(&choice.label, MAX_OPTION_LENGTH))
+                            .value(&choice.uri)
+                            .default_selection(index == selected);
+                        if let Some(description) = &choice.description {
+                            option.description(truncate(description, MAX_OPTION_LENGTH));
+                        }
+                        option
+                    });
+                }
+                options
+            })
+        })
+    });
+
+    if let Some(choice) = choices.get(selected) {
+        components.create_action_row(|row| {
+            row.create_button(|button| {
+                button
+                    .custom_id(format!("{}{}", PLAY_PREFIX, choice.uri))
+                    .label("Play")
+                    .style(ButtonStyle::Primary)
+            })
+            .create_button(|button| {
+                button
+                    .custom_id(format!("{}{}", QUEUE_PREFIX, choice.uri))
+                    .label("Queue")
+                    .style(ButtonStyle::Secondary)
+            })
+        });
+    }
+
+    components
+}
+
+// Read the results back out of a search message, so it can be rebuilt when
+// the selection changes
+pub fn choices_from_message(rows: &[ActionRow]) -> Vec<Choice> {
+    rows.iter()
+        .flat_map(|row| row.components.iter())
+        .filter_map(|component| match component {
+            ActionRowComponent::SelectMenu(menu) if menu.custom_id.as_deref() == Some(SELECT_ID) => Some(menu),
+            _ => None,
+        })
+        .flat_map(|menu| menu.options.iter())
+        .map(|option| Choice {
+            label: option.label.clone(),
+            description: option.description.clone(),
+            uri: option.value.clone(),
+        })
+        .collect()
+}
+
+// The action and spotify uri a play or queue button stands for
+pub fn parse_button(custom_id: &str) -> Option<(Action, &str)> {
+    if let Some(uri) = custom_id.strip_prefix(PLAY_PREFIX) {
+        Some((Action::Play, uri))
+    } else {
+        custom_id.strip_prefix(QUEUE_PREFIX).map(|uri| (Action::Queue, uri))
     }
 }
 
 pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
     command
         .name("search")
-        .description("Search for tracks, albums, and playlists through spotify")
+        .description("Search spotify, then pick a result to play or queue")
         .create_option(|option| {
             option
                 .name("name")
                 .description("name of music to search for")
                 .kind(CommandOptionType::String)
+                .set_autocomplete(true)
                 .required(true)
         })
         .create_option(|option| {
@@ -90,3 +215,24 @@ pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicatio
                 .required(false)
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FakeSpotify;
+    use crate::backend::fake;
+    use crate::commands::testing::string;
+
+    #[tokio::test]
+    async fn lists_tracks_without_artists() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        let mut track = fake::track("2wUkRqBa5fExNkvBGSFTWh", "Voice Memo", "Nobody", 60);
+        track.artists.clear();
+        track.album.name = "Local Files".to_string();
+        spotify.add_track(track);
+
+        let response = run(&[string("name", "voice memo")], &spotify, 10).await.unwrap();
+
+        assert_eq!(response.content.as_deref(), Some("Search results for 'voice memo'\n1. Voice Memo \u{2014} Local Files\n"));
+    }
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::interaction::application_command::{
+    CommandDataOption,
+    CommandDataOptionValue,
+};
+
+use rspotify::{
+    model::PlayableItem,
+};
+
+use crate::CommandError;
+use crate::backend::SpotifyBackend;
+use crate::format_duration;
+use crate::values_from_options;
+use crate::str_from_value;
+
+// Where to seek to, from the position option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    To(Duration),
+    Forward(Duration),
+    Back(Duration),
+}
+
+pub async fn run<S: SpotifyBackend + ?Sized>(options: &[CommandDataOption], spotify: &S) -> Result<String, CommandError> {
+    let values: Vec<&CommandDataOptionValue> = values_from_options(options)?;
+
+    let position: &str = str_from_value(&values, 0, None)?;
+
+    let target = parse_target(position)
+        .ok_or("Use a position like 1:30, +30s or -15s")?;
+
+    let playback = spotify.current_playback()
+        .await?
+        .ok_or("No current playback")?;
+
+    let duration = match playback.item.as_ref().ok_or("Nothing is playing")? {
+        PlayableItem::Track(track) => track.duration,
+        PlayableItem::Episode(episode) => episode.duration,
+    };
+    let progress = playback.progress.unwrap_or_default();
+
+    let position = match target {
+        Target::To(position) => position,
+        Target::Forward(offset) => progress + offset,
+        Target::Back(offset) => progress.saturating_sub(offset),
+    }.min(duration);
+
+    let position_ms: u32 = position.as_millis().try_into().map_err(|_| "Position is too far")?;
+    spotify.seek_track(position_ms, None).await?;
+
+    Ok(format!("Seeked to {} / {}", format_duration(position), format_duration(duration)))
+}
+
+// "1:30" or "1:02:03" seeks to that point, "+30s" and "-15s" seek relative to
+// where playback is now
+fn parse_target(string: &str) -> Option<Target> {
+    let string = string.trim();
+
+    if let Some(offset) = string.strip_prefix('+') {
+        return parse_offset(offset).map(Target::Forward);
+    }
+    if let Some(offset) = string.strip_prefix('-') {
+        return parse_offset(offset).map(Target::Back);
+    }
+
+    parse_offset(string).map(Target::To)
+}
+
+// m:ss, h:mm:ss, or a number of seconds with an optional s
+fn parse_offset(string: &str) -> Option<Duration> {
+    let string = string.trim();
+
+    if string.contains(':') {
+        let mut seconds: u64 = 0;
+        for part in string.split(':') {
+            seconds = seconds.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+        }
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let seconds = string.strip_suffix('s').unwrap_or(string);
+    seconds.parse::<u64>().ok().map(Duration::from_secs)
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("seek")
+        .description("Jump to a point in the current track")
+        .create_option(|option| {
+            option
+                .name("position")
+                .description("a time like 1:30, or +30s / -15s from now")
+                .kind(CommandOptionType::String)
+                .required(true)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FakeSpotify;
+    use crate::commands::testing::string;
+
+    #[test]
+    fn parses_targets() {
+        let secs = Duration::from_secs;
+        let cases = [
+            ("1:30", Some(Target::To(secs(90)))),
+            ("0:05", Some(Target::To(secs(5)))),
+            ("1:02:03", Some(Target::To(secs(3723)))),
+            ("45", Some(Target::To(secs(45)))),
+            ("45s", Some(Target::To(secs(45)))),
+            (" 1:30 ", Some(Target::To(secs(90)))),
+            ("+30s", Some(Target::Forward(secs(30)))),
+            ("+1:00", Some(Target::Forward(secs(60)))),
+            ("-15s", Some(Target::Back(secs(15)))),
+            ("-15", Some(Target::Back(secs(15)))),
+            ("", None),
+            ("+", None),
+            ("abc", None),
+            ("1:xx", None),
+            ("1::30", None),
+            ("--15s", None),
+            ("+-15s", None),
+            ("15m", None),
+            ("1.5", None),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_target(input), expected, "parsing {:?}", input);
+        }
+    }
+
+    async fn seek(spotify: &FakeSpotify, position: &str) -> String {
+        run(&[string("position", position)], spotify).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn seeks_within_the_track() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        spotify.state().progress = Duration::from_secs(10);
+
+        assert_eq!(seek(&spotify, "+30s").await, "Seeked to 0:40 / 3:33");
+        assert_eq!(seek(&spotify, "1:30").await, "Seeked to 1:30 / 3:33");
+        assert_eq!(spotify.state().progress, Duration::from_secs(90));
+    }
+
+    #[tokio::test]
+    async fn clamps_to_the_track() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        spotify.state().progress = Duration::from_secs(10);
+
+        assert_eq!(seek(&spotify, "-15s").await, "Seeked to 0:00 / 3:33");
+        assert_eq!(seek(&spotify, "+10:00").await, "Seeked to 3:33 / 3:33");
+        assert_eq!(seek(&spotify, "59:00").await, "Seeked to 3:33 / 3:33");
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_positions() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+
+        assert!(run(&[string("position", "soon")], &spotify).await.is_err());
+        assert_eq!(spotify.state().progress, Duration::ZERO);
+    }
+}
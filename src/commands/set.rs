@@ -7,14 +7,13 @@ use serenity::model::prelude::interaction::application_command::{
 };
 
 use rspotify::{
-    AuthCodeSpotify,
-    clients::OAuthClient,
     model::enums::misc::RepeatState,
 };
 
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
 
-pub async fn run(options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
+pub async fn run<S: SpotifyBackend + ?Sized>(options: &[CommandDataOption], spotify: &S) -> Result<String, CommandError> {
     let option = options
         .get(0)
         .ok_or("No argument")?;
@@ -29,16 +28,17 @@ pub async fn run(options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Re
 
     match option.name.as_str() {
         "repeat" => {
-            if let CommandDataOptionValue::Boolean(value) = *option_value {
-                if value {
-                    spotify.repeat(RepeatState::Context, None).await?;
-                    Ok("Set repeat to on".to_string())
-                } else {
-                    spotify.repeat(RepeatState::Off, None).await?;
-                    Ok("Set repeat to off".to_string())
-                }
+            if let CommandDataOptionValue::String(value) = option_value {
+                let state = match value.as_str() {
+                    "off" => RepeatState::Off,
+                    "context" => RepeatState::Context,
+                    "track" => RepeatState::Track,
+                    _ => return Err(CommandError::from("Expected off, context or track")),
+                };
+                spotify.repeat(state, None).await?;
+                Ok(format!("Set repeat to {}", value))
             } else {
-                Err(CommandError::from("Expected boolean value"))
+                Err(CommandError::from("Expected string value"))
             }
         }
         "shuffle" => {
@@ -68,13 +68,16 @@ pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicatio
         .create_option(|option| {
             option
                 .name("repeat")
-                .description("enable or disable repeat")
+                .description("repeat nothing, the album or playlist, or the current track")
                 .kind(CommandOptionType::SubCommand)
                 .create_sub_option(|option| {
                     option
-                        .name("bool")
-                        .description("true/false")
-                        .kind(CommandOptionType::Boolean)
+                        .name("mode")
+                        .description("off, context or track")
+                        .kind(CommandOptionType::String)
+                        .add_string_choice("off", "off")
+                        .add_string_choice("context", "context")
+                        .add_string_choice("track", "track")
                         .required(true)
                 })
         })
@@ -1,42 +1,221 @@
 use log::warn;
 use serenity::builder::CreateApplicationCommand;
+use serenity::builder::CreateComponents;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::id::UserId;
 use serenity::model::prelude::command::CommandOptionType;
 use serenity::model::prelude::interaction::application_command::{
     CommandDataOption,
     CommandDataOptionValue,
 };
 
+use tokio::sync::Mutex;
+
 use rspotify::{
-    AuthCodeSpotify,
-    clients::OAuthClient,
+    model::PlayableItem,
+    prelude::Id,
 };
 
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
+use crate::local_queue::LocalQueue;
+use crate::local_queue::play_next;
+use crate::vote::SkipVotes;
+use crate::vote::VoteResult;
+use crate::vote::VoteThreshold;
 use crate::values_from_options;
 use crate::int_from_value;
 
-pub async fn run(options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
+pub const VOTE_BUTTON_ID: &str = "skip_vote";
+
+pub async fn run<S: SpotifyBackend + ?Sized>(
+    options: &[CommandDataOption],
+    spotify: &S,
+    queue: &Mutex<LocalQueue>,
+    votes: &Mutex<SkipVotes>,
+    threshold: VoteThreshold,
+    user: UserId,
+    listeners: Option<usize>,
+) -> Result<String, CommandError> {
     let values: Vec<&CommandDataOptionValue> = values_from_options(options)?;
 
     let repeat = int_from_value(&values, 0, Some(1))?;
 
-    for _ in 0..repeat {
-        spotify.next_track(None).await?;
+    if threshold == VoteThreshold::Off {
+        return skip(spotify, queue, repeat).await;
     }
-    Ok("Skipped tracks".to_string())
+
+    vote(spotify, queue, votes, threshold, user, listeners).await
+}
+
+// Count a vote from `user` to skip the current track, skipping it once enough
+// people agree
+pub async fn vote<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    queue: &Mutex<LocalQueue>,
+    votes: &Mutex<SkipVotes>,
+    threshold: VoteThreshold,
+    user: UserId,
+    listeners: Option<usize>,
+) -> Result<String, CommandError> {
+    let item = spotify
+        .current_playback()
+        .await?
+        .and_then(|playback| playback.item)
+        .ok_or("Nothing is playing")?;
+
+    let track_id = match &item {
+        PlayableItem::Track(track) => track.id.as_ref().ok_or("No track id")?.id().to_string(),
+        PlayableItem::Episode(episode) => episode.id.id().to_string(),
+    };
+
+    let required = threshold
+        .required(listeners)
+        .ok_or("Join the voice channel to vote to skip")?;
+
+    let result = votes.lock().await.vote(&track_id, user, required);
+
+    match result {
+        VoteResult::Passed => skip(spotify, queue, 1).await,
+        VoteResult::Counted { votes, required } => {
+            Ok(format!("Vote to skip: {votes}/{required}"))
+        }
+        VoteResult::AlreadyVoted { votes, required } => {
+            Ok(format!("You have already voted to skip: {votes}/{required}"))
+        }
+    }
+}
+
+pub async fn skip<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    queue: &Mutex<LocalQueue>,
+    repeat: i64,
+) -> Result<String, CommandError> {
+    // drop the entries being skipped over, then start the one after them
+    let mut dropped = 0;
+    {
+        let mut queue = queue.lock().await;
+        for _ in 1..repeat {
+            if queue.pop().is_none() {
+                break;
+            }
+            dropped += 1;
+        }
+    }
+
+    match play_next(queue, spotify).await? {
+        Some(entry) => Ok(format!("Skipped to {} \u{2014} {}", entry.artist, entry.name)),
+        None => {
+            // the dropped entries count towards the skips
+            for _ in 0..repeat - dropped {
+                spotify.next_track(None).await?;
+            }
+            Ok("Skipped tracks".to_string())
+        }
+    }
+}
+
+pub fn vote_button(components: &mut CreateComponents) -> &mut CreateComponents {
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button
+                .custom_id(VOTE_BUTTON_ID)
+                .label("Vote to skip")
+                .style(ButtonStyle::Primary)
+        })
+    })
 }
 
 pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
     command
         .name("skip")
-        .description("Skip to next song")
+        .description("Skip to next song, or vote to skip it")
         .create_option(|option| {
             option
                 .name("count")
-                .description("Number of songs to skip")
+                .description("Number of songs to skip, when voting is off")
                 .kind(CommandOptionType::Integer)
                 .min_int_value(1)
                 .max_int_value(10)
                 .required(false)
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FakeSpotify;
+    use crate::backend::fake;
+    use crate::local_queue::QueueEntry;
+
+    fn entry(id: &str, name: &str) -> QueueEntry {
+        QueueEntry::from_track(&fake::track(id, name, "Someone", 180), UserId(1)).unwrap()
+    }
+
+    fn playing(spotify: &FakeSpotify) -> String {
+        spotify.state().item.as_ref().unwrap().name.clone()
+    }
+
+    #[tokio::test]
+    async fn skips_through_spotify_without_queued_tracks() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        let queue = Mutex::new(LocalQueue::new());
+
+        assert_eq!(skip(&spotify, &queue, 1).await.unwrap(), "Skipped tracks");
+        assert_eq!(playing(&spotify), "Together Forever");
+    }
+
+    #[tokio::test]
+    async fn skipped_queue_entries_count_towards_the_skips() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        let queue = Mutex::new(LocalQueue::new());
+        queue.lock().await.push(entry("0b11D9D0hMOYCIMN3OKreM", "Blue (Da Ba Dee)"));
+
+        // one skip drops the queued track, the other leaves the album's first
+        assert_eq!(skip(&spotify, &queue, 2).await.unwrap(), "Skipped tracks");
+        assert!(queue.lock().await.entries().is_empty());
+        assert_eq!(playing(&spotify), "Together Forever");
+    }
+
+    #[tokio::test]
+    async fn skips_to_the_next_queued_track() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        let queue = Mutex::new(LocalQueue::new());
+        queue.lock().await.push(entry("7GhIk7Il098yCjg4BQjzvb", "Together Forever"));
+        queue.lock().await.push(entry("0b11D9D0hMOYCIMN3OKreM", "Blue (Da Ba Dee)"));
+
+        let reply = skip(&spotify, &queue, 2).await.unwrap();
+
+        assert_eq!(reply, "Skipped to Someone \u{2014} Blue (Da Ba Dee)");
+        assert_eq!(playing(&spotify), "Blue (Da Ba Dee)");
+    }
+
+    #[tokio::test]
+    async fn votes_skip_once_enough_people_agree() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        let queue = Mutex::new(LocalQueue::new());
+        let votes = Mutex::new(SkipVotes::new());
+        let threshold = VoteThreshold::Fraction(0.5);
+
+        let reply = vote(&spotify, &queue, &votes, threshold, UserId(1), Some(4)).await.unwrap();
+        assert_eq!(reply, "Vote to skip: 1/2");
+        let reply = vote(&spotify, &queue, &votes, threshold, UserId(1), Some(4)).await.unwrap();
+        assert_eq!(reply, "You have already voted to skip: 1/2");
+        assert_eq!(playing(&spotify), "Never Gonna Give You Up");
+
+        vote(&spotify, &queue, &votes, threshold, UserId(2), Some(4)).await.unwrap();
+        assert_eq!(playing(&spotify), "Together Forever");
+    }
+
+    #[tokio::test]
+    async fn share_votes_need_a_voice_channel() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        let queue = Mutex::new(LocalQueue::new());
+        let votes = Mutex::new(SkipVotes::new());
+
+        let result = vote(&spotify, &queue, &votes, VoteThreshold::Fraction(0.5), UserId(1), None).await;
+
+        assert!(result.is_err());
+        assert!(!votes.lock().await.is_open());
+    }
+}
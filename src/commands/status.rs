@@ -2,15 +2,14 @@ use std::time::Duration;
 
 use log::warn;
 use serenity::builder::CreateApplicationCommand;
+use serenity::builder::CreateComponents;
+use serenity::builder::CreateEmbed;
+use serenity::model::application::component::ButtonStyle;
 use serenity::model::prelude::interaction::application_command::{
     CommandDataOption,
 };
 
 use rspotify::{
-    AuthCodeSpotify,
-    clients::BaseClient,
-    clients::OAuthClient,
-    model::enums::types::AdditionalType,
     model::enums::types::Type,
     model::enums::misc::RepeatState,
     model::PlayableItem,
@@ -22,24 +21,18 @@ use rspotify::{
 };
 
 use crate::CommandError;
+use crate::backend::SpotifyBackend;
+use crate::commands;
+use crate::commands::previous;
+use crate::response::Response;
+use crate::response::item_embed;
+use crate::response::state_colour;
 
-pub async fn run(_options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> Result<String, CommandError> {
-    let playback = spotify.current_playback(None, None::<Vec<&AdditionalType>>)
+pub async fn run<S: SpotifyBackend + ?Sized>(_options: &[CommandDataOption], spotify: &S) -> Result<Response, CommandError> {
+    let playback = spotify.current_playback()
         .await?
         .ok_or("No current playback")?;
 
-    // This will create a message with the format:
-    //   {is_playing}
-    //   {_type} {type_name}
-    //   {name} --- {artist}
-    //   {progress} / {duration}
-    //   Volume: {volume}%
-    //   Shuffle: {shuffle}
-    //   Repeat: {repeat}
-
-    // increase capacity when adding more lines
-    let mut status: Vec<String> = Vec::with_capacity(7);
-
     let device: Device = playback.device;
     let repeat: RepeatState = playback.repeat_state;
     let shuffle: bool = playback.shuffle_state;
@@ -48,14 +41,22 @@ pub async fn run(_options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> R
     let is_playing: bool = playback.is_playing;
     let item: Option<PlayableItem> = playback.item;
 
-    // is_playing line
+    let mut embed = match &item {
+        Some(item) => item_embed(item, playback_progress, is_playing),
+        None => {
+            let mut embed = CreateEmbed::default();
+            embed.title("Nothing playing").colour(state_colour(is_playing));
+            embed
+        }
+    };
+
     if is_playing {
-        status.push("> Playing".to_string());
+        embed.author(|author| author.name("Playing"));
     } else {
-        status.push("> Paused".to_string());
+        embed.author(|author| author.name("Paused"));
     }
 
-    // type line
+    // context field
     'a: { if let Some(context) = playback_context {
         let _type: Type = context._type;
         let uri: String = context.uri;
@@ -63,75 +64,161 @@ pub async fn run(_options: &[CommandDataOption], spotify: &AuthCodeSpotify) -> R
         let name = match &_type {
             Type::Artist => spotify.artist(ArtistId::from_uri(&uri)?).await?.name,
             Type::Album => spotify.album(AlbumId::from_uri(&uri)?).await?.name,
-            Type::Playlist => spotify.playlist(PlaylistId::from_uri(&uri)?, None, None).await?.name,
+            Type::Playlist => spotify.playlist(PlaylistId::from_uri(&uri)?).await?.name,
             _ => break 'a,
         }; 
 
-        status.push(format!("{_type:?} {name}"));
+        embed.field(format!("{_type:?}"), name, false);
     } }
 
-    // unpack item info
-    let (item_name, item_artist, item_duration) = match &item {
-        Some(PlayableItem::Track(item)) => {
-            (Some(&item.name), Some(&item.artists[0].name), Some(&item.duration)) 
-        }
-        Some(PlayableItem::Episode(item)) => {
-            (Some(&item.name), None, Some(&item.duration))
-        }
-        None => {
-            (None, None, None)
-        }
-    };
+    if let Some(volume) = device.volume_percent {
+        embed.field("Volume", format!("{volume}%"), true);
+    }
 
-    // playing track line
-    match (item_name, item_artist) {
-        (Some(name), Some(artist)) => status.push(format!("{name} \u{2014} {artist}")),
-        (Some(name), None) => status.push(format!("{name}")),
-        (None, _) => (),
+    if shuffle {
+        embed.field("Shuffle", "On", true);
+    } else {
+        embed.field("Shuffle", "Off", true);
     }
 
-    // progress line
-    if let (Some(progress), Some(duration)) = (playback_progress, item_duration) {
-        let progress_seconds = progress.as_secs();
-        let duration_seconds = duration.as_secs();
-        if duration.as_secs() / (60 * 60) > 0 {
-            status.push(format!("{}:{:0>2}:{:0>2} / {}:{:0>2}:{:0>2}",
-                progress_seconds / 60 / 60,
-                progress_seconds / 60 % 60,
-                progress_seconds % 60,
-                duration_seconds / 60 / 60,
-                duration_seconds / 60 % 60,
-                duration_seconds % 60,
-            ));
-        } else {
-            status.push(format!("{}:{:0>2} / {}:{:0>2}",
-                progress_seconds / 60 % 60,
-                progress_seconds % 60,
-                duration_seconds / 60 % 60,
-                duration_seconds % 60,
-            ));
+    match repeat {
+        RepeatState::Off => embed.field("Repeat", "Off", true),
+        RepeatState::Context => embed.field("Repeat", "Context", true),
+        RepeatState::Track => embed.field("Repeat", "Track", true),
+    };
+
+    embed.footer(|footer| footer.text(format!("on {}", device.name)));
+
+    Ok(Response::embed(embed).with_components(controls(is_playing)))
+}
+
+// spotify takes a moment to report a change, so wait before showing it
+pub const SETTLE: Duration = Duration::from_millis(500);
+
+const VOLUME_STEP: u8 = 10;
+
+// A playback control button under a status message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Previous,
+    Toggle,
+    Next,
+    Shuffle,
+    Repeat,
+    VolumeDown,
+    VolumeUp,
+}
+
+impl Control {
+    const ALL: [Control; 7] = [
+        Control::Previous,
+        Control::Toggle,
+        Control::Next,
+        Control::Shuffle,
+        Control::Repeat,
+        Control::VolumeDown,
+        Control::VolumeUp,
+    ];
+
+    fn custom_id(&self) -> &'static str {
+        match self {
+            Control::Previous => "status_previous",
+            Control::Toggle => "status_toggle",
+            Control::Next => "status_next",
+            Control::Shuffle => "status_shuffle",
+            Control::Repeat => "status_repeat",
+            Control::VolumeDown => "status_volume_down",
+            Control::VolumeUp => "status_volume_up",
         }
     }
 
-    // volume line
-    if let Some(volume) = device.volume_percent {
-        status.push(format!("Volume: {volume}%"));
+    pub fn from_custom_id(custom_id: &str) -> Option<Self> {
+        Control::ALL.into_iter().find(|control| control.custom_id() == custom_id)
     }
 
-    // shuffle line
-    if shuffle {
-        status.push("Shuffle: On".to_string());
-    } else {
-        status.push("Shuffle: Off".to_string());
+    // The slash command doing the same thing, whose permissions apply
+    pub fn command(&self) -> &'static str {
+        match self {
+            Control::Previous => "previous",
+            Control::Toggle => "playback",
+            Control::Next => "skip",
+            Control::Shuffle | Control::Repeat | Control::VolumeDown | Control::VolumeUp => "set",
+        }
     }
+}
 
-    // repeat line
-    match repeat {
-        RepeatState::Off => status.push("Repeat: Off".to_string()),
-        _ => status.push("Repeat: On".to_string()),
-    }
+pub fn controls(is_playing: bool) -> CreateComponents {
+    let mut components = CreateComponents::default();
+
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button.custom_id(Control::Previous.custom_id()).label("\u{23EE}").style(ButtonStyle::Secondary)
+        })
+        .create_button(|button| {
+            let label = if is_playing { "\u{23F8}" } else { "\u{25B6}" };
+            button.custom_id(Control::Toggle.custom_id()).label(label).style(ButtonStyle::Primary)
+        })
+        .create_button(|button| {
+            button.custom_id(Control::Next.custom_id()).label("\u{23ED}").style(ButtonStyle::Secondary)
+        })
+    });
+
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button.custom_id(Control::Shuffle.custom_id()).label("Shuffle").style(ButtonStyle::Secondary)
+        })
+        .create_button(|button| {
+            button.custom_id(Control::Repeat.custom_id()).label("Repeat").style(ButtonStyle::Secondary)
+        })
+        .create_button(|button| {
+            button.custom_id(Control::VolumeDown.custom_id()).label("Vol -").style(ButtonStyle::Secondary)
+        })
+        .create_button(|button| {
+            button.custom_id(Control::VolumeUp.custom_id()).label("Vol +").style(ButtonStyle::Secondary)
+        })
+    });
 
-    Ok(status.join("\n> "))
+    components
+}
+
+// Carry out a control. Next only comes here for linked accounts, the shared
+// account skips through votes.
+pub async fn control<S: SpotifyBackend + ?Sized>(spotify: &S, control: Control) -> Result<String, CommandError> {
+    let playback = spotify.current_playback()
+        .await?
+        .ok_or("No current playback")?;
+
+    match control {
+        Control::Previous => previous::run(&[], spotify).await,
+        Control::Toggle if playback.is_playing => commands::playback::pause(spotify).await,
+        Control::Toggle => commands::playback::resume(spotify).await,
+        Control::Shuffle => {
+            spotify.shuffle(!playback.shuffle_state, None).await?;
+            Ok(format!("Set shuffle to {}", !playback.shuffle_state))
+        }
+        Control::Repeat => {
+            let (next, name) = match playback.repeat_state {
+                RepeatState::Off => (RepeatState::Context, "context"),
+                RepeatState::Context => (RepeatState::Track, "track"),
+                RepeatState::Track => (RepeatState::Off, "off"),
+            };
+            spotify.repeat(next, None).await?;
+            Ok(format!("Set repeat to {}", name))
+        }
+        Control::VolumeDown | Control::VolumeUp => {
+            let volume = playback.device.volume_percent.ok_or("This device has no volume control")?;
+            let volume = match control {
+                Control::VolumeUp => (volume as u8).saturating_add(VOLUME_STEP).min(100),
+                _ => (volume as u8).saturating_sub(VOLUME_STEP),
+            };
+            spotify.volume(volume, None).await?;
+            Ok(format!("Set volume to {}", volume))
+        }
+        Control::Next => {
+            spotify.next_track(None).await?;
+            Ok("Skipped a track".to_string())
+        }
+    }
 }
 
 pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
@@ -139,3 +226,85 @@ pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicatio
         .name("status")
         .description("Show playback status")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FakeSpotify;
+
+    // the last field called `name`, context fields come after the track's own
+    fn field(embed: &CreateEmbed, name: &str) -> Option<String> {
+        embed.0.get("fields")?
+            .as_array()?
+            .iter()
+            .rev()
+            .find(|field| field["name"] == name)
+            .and_then(|field| field["value"].as_str())
+            .map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn shows_the_track_context_and_settings() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        spotify.state().repeat_state = RepeatState::Track;
+
+        let response = run(&[], &spotify).await.unwrap();
+        let embed = response.embed.unwrap();
+
+        assert_eq!(embed.0["title"], "Never Gonna Give You Up");
+        assert_eq!(embed.0["author"]["name"], "Playing");
+        assert_eq!(field(&embed, "Album").as_deref(), Some("Whenever You Need Somebody"));
+        assert_eq!(field(&embed, "Shuffle").as_deref(), Some("Off"));
+        assert_eq!(field(&embed, "Repeat").as_deref(), Some("Track"));
+        assert_eq!(field(&embed, "Volume").as_deref(), Some("50%"));
+        assert!(response.components.is_some());
+    }
+
+    #[tokio::test]
+    async fn needs_playback() {
+        let spotify = FakeSpotify::with_demo_catalog();
+        spotify.state().devices[0].is_active = false;
+
+        assert!(matches!(run(&[], &spotify).await, Err(CommandError::SimpleError(_))));
+    }
+
+    #[tokio::test]
+    async fn reports_expired_logins_and_refusals() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+
+        spotify.state().failures.push_back(401);
+        assert!(matches!(run(&[], &spotify).await, Err(CommandError::AuthExpired(_))));
+
+        spotify.state().failures.push_back(403);
+        assert!(matches!(run(&[], &spotify).await, Err(CommandError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn controls_change_playback() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+
+        assert_eq!(control(&spotify, Control::Toggle).await.unwrap(), "Playback paused");
+        assert!(!spotify.state().is_playing);
+
+        control(&spotify, Control::Repeat).await.unwrap();
+        assert_eq!(spotify.state().repeat_state, RepeatState::Context);
+        control(&spotify, Control::Repeat).await.unwrap();
+        control(&spotify, Control::Repeat).await.unwrap();
+        assert_eq!(spotify.state().repeat_state, RepeatState::Off);
+
+        control(&spotify, Control::VolumeUp).await.unwrap();
+        assert_eq!(spotify.state().devices[0].volume_percent, Some(60));
+
+        control(&spotify, Control::Next).await.unwrap();
+        assert_eq!(spotify.state().item.as_ref().unwrap().name, "Together Forever");
+    }
+
+    #[tokio::test]
+    async fn controls_need_an_active_device() {
+        let spotify = FakeSpotify::playing_demo_album().await;
+        // spotify refusing player requests, as when the device has gone away
+        spotify.state().failures.push_back(404);
+
+        assert!(matches!(control(&spotify, Control::Shuffle).await, Err(CommandError::NoActiveDevice(_))));
+    }
+}
@@ -0,0 +1,24 @@
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::id::UserId;
+use serenity::model::prelude::interaction::application_command::{
+    CommandDataOption,
+};
+
+use crate::CommandError;
+use crate::accounts::Accounts;
+
+pub async fn run(_options: &[CommandDataOption], accounts: Option<&Accounts>, user: UserId) -> Result<String, CommandError> {
+    let accounts = accounts.ok_or("Account linking is not set up on this bot")?;
+
+    if accounts.unlink(user).await {
+        Ok("Your spotify account is no longer linked".to_string())
+    } else {
+        Err(CommandError::from("You haven't linked a spotify account"))
+    }
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("unlink")
+        .description("Unlink your spotify account")
+}
@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+
+use crate::vote::VoteThreshold;
+
+const DEFAULT_PATH: &str = "fishify.toml";
+
+// Everything the bot can be configured with. Loaded from a toml file (see
+// `fishify.example.toml`), with environment variables taking priority.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub discord_token: String,
+    // guilds to register commands in, every guild with settings included.
+    // Empty when commands are registered globally.
+    pub guild_ids: Vec<GuildId>,
+    // register commands globally instead, so they work in any guild the bot joins
+    pub global_commands: bool,
+    pub admin_user: Option<UserId>,
+    pub spotify: SpotifyConfig,
+    pub search_results: u32,
+    // most tracks queued from one album or playlist
+    pub queue_max_tracks: u32,
+    pub poll_interval: Duration,
+    pub webhook: Option<WebhookConfig>,
+    pub features: Features,
+    // settings for guilds without their own, and for DMs
+    pub defaults: GuildSettings,
+    pub guilds: HashMap<GuildId, GuildSettings>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GuildSettings {
+    pub default_device: Option<String>,
+    // commands are only answered in these channels, any channel if empty
+    pub allowed_channels: Vec<ChannelId>,
+    // mutating commands need the DJ role or to be on the allow-list, if
+    // either is set. DJs also skip without a vote.
+    pub dj_role: Option<RoleId>,
+    pub allowed_users: Vec<UserId>,
+    pub skip_vote_threshold: VoteThreshold,
+    pub account: AccountMode,
+}
+
+// Whose spotify account search, play, queue and status act on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountMode {
+    // the bot's own account, playing on the shared speaker
+    Shared,
+    // the account the person running the command linked with /link
+    Linked,
+}
+
+impl FromStr for AccountMode {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "shared" => Ok(AccountMode::Shared),
+            "linked" => Ok(AccountMode::Linked),
+            _ => Err(format!("expected \"shared\" or \"linked\", found \"{}\"", string)),
+        }
+    }
+}
+
+impl GuildSettings {
+    pub fn allows_channel(&self, channel_id: ChannelId) -> bool {
+        self.allowed_channels.is_empty() || self.allowed_channels.contains(&channel_id)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpotifyConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub token_cache: PathBuf,
+    // where spotify sends people back to after /link, it needs its own port
+    pub link_redirect_uri: Option<String>,
+    pub user_token_dir: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub port: u16,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Features {
+    // run against the in-memory catalog instead of a spotify account
+    pub fake_spotify: bool,
+    pub skip_votes: bool,
+    pub webhook: bool,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    // a key that is missing or has a bad value, named the way the file spells it
+    Invalid { key: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, error) => write!(f, "could not read {}: {}", path.display(), error),
+            ConfigError::Parse(path, error) => write!(f, "could not parse {}: {}", path.display(), error),
+            ConfigError::Invalid { key, reason } => write!(f, "invalid `{}`: {}", key, reason),
+        }
+    }
+}
+
+fn invalid(key: impl Into<String>, reason: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid { key: key.into(), reason: reason.into() }
+}
+
+// The file as written, before defaults and validation
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    discord: RawDiscord,
+    spotify: RawSpotify,
+    playback: RawPlayback,
+    search: RawSearch,
+    queue: RawQueue,
+    skip_vote: RawSkipVote,
+    webhook: RawWebhook,
+    features: RawFeatures,
+    // keyed by guild id, eg [guilds.123456789]
+    guilds: HashMap<String, RawGuild>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawDiscord {
+    token: Option<String>,
+    guild_ids: Vec<u64>,
+    global_commands: Option<bool>,
+    admin_user_id: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawSpotify {
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    redirect_uri: Option<String>,
+    token_cache: Option<PathBuf>,
+    default_device: Option<String>,
+    link_redirect_uri: Option<String>,
+    user_token_dir: Option<PathBuf>,
+    account: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawPlayback {
+    poll_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawSearch {
+    results: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawQueue {
+    max_tracks: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawSkipVote {
+    // "off", a count or a share, as a string or a number
+    threshold: Option<toml::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawWebhook {
+    port: Option<u16>,
+    secret: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawGuild {
+    default_device: Option<String>,
+    allowed_channels: Vec<u64>,
+    dj_role: Option<u64>,
+    allowed_users: Vec<u64>,
+    skip_vote_threshold: Option<toml::Value>,
+    account: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawFeatures {
+    fake_spotify: Option<bool>,
+    skip_votes: Option<bool>,
+    webhook: Option<bool>,
+}
+
+impl Config {
+    // Settings for the guild a command came from
+    pub fn guild(&self, guild_id: Option<GuildId>) -> &GuildSettings {
+        guild_id
+            .and_then(|guild_id| self.guilds.get(&guild_id))
+            .unwrap_or(&self.defaults)
+    }
+
+    // Read FISHIFY_CONFIG, or fishify.toml if it exists, then apply any
+    // environment overrides
+    pub fn load() -> Result<Self, ConfigError> {
+        let (path, required) = match env::var("FISHIFY_CONFIG") {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_PATH), false),
+        };
+
+        let mut raw = match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|why| ConfigError::Parse(path.clone(), why))?,
+            Err(why) if why.kind() == io::ErrorKind::NotFound && !required => RawConfig::default(),
+            Err(why) => return Err(ConfigError::Read(path, why)),
+        };
+
+        raw.apply_env()?;
+        raw.validate()
+    }
+}
+
+// Parse an environment variable into `key`, naming both if it is bad
+fn env_override<T: FromStr>(var: &str, key: &str, value: &mut Option<T>) -> Result<(), ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    if let Ok(string) = env::var(var) {
+        let parsed = string
+            .trim()
+            .parse()
+            .map_err(|why| invalid(key, format!("{} from {}", why, var)))?;
+        *value = Some(parsed);
+    }
+    Ok(())
+}
+
+impl RawConfig {
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        env_override("DISCORD_TOKEN", "discord.token", &mut self.discord.token)?;
+        env_override("ADMIN_USER_ID", "discord.admin_user_id", &mut self.discord.admin_user_id)?;
+        env_override("GLOBAL_COMMANDS", "discord.global_commands", &mut self.discord.global_commands)?;
+
+        // GUILD_ID may list several guilds separated by commas
+        if let Ok(ids) = env::var("GUILD_ID") {
+            self.discord.guild_ids = ids
+                .split(',')
+                .map(|id| id.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|why| invalid("discord.guild_ids", format!("{} from GUILD_ID", why)))?;
+        }
+
+        env_override("RSPOTIFY_CLIENT_ID", "spotify.client_id", &mut self.spotify.client_id)?;
+        env_override("RSPOTIFY_CLIENT_SECRET", "spotify.client_secret", &mut self.spotify.client_secret)?;
+        env_override("RSPOTIFY_REDIRECT_URI", "spotify.redirect_uri", &mut self.spotify.redirect_uri)?;
+        env_override("SPOTIFY_TOKEN_CACHE", "spotify.token_cache", &mut self.spotify.token_cache)?;
+        env_override("DEFAULT_DEVICE", "spotify.default_device", &mut self.spotify.default_device)?;
+        env_override("LINK_REDIRECT_URI", "spotify.link_redirect_uri", &mut self.spotify.link_redirect_uri)?;
+        env_override("USER_TOKEN_DIR", "spotify.user_token_dir", &mut self.spotify.user_token_dir)?;
+        env_override("SPOTIFY_ACCOUNT", "spotify.account", &mut self.spotify.account)?;
+        env_override("PLAYBACK_POLL_SECS", "playback.poll_secs", &mut self.playback.poll_secs)?;
+        env_override("SEARCH_RESULTS", "search.results", &mut self.search.results)?;
+        env_override("QUEUE_MAX_TRACKS", "queue.max_tracks", &mut self.queue.max_tracks)?;
+        env_override("WEBHOOK_PORT", "webhook.port", &mut self.webhook.port)?;
+        env_override("WEBHOOK_SECRET", "webhook.secret", &mut self.webhook.secret)?;
+
+        if let Ok(threshold) = env::var("SKIP_VOTE_THRESHOLD") {
+            self.skip_vote.threshold = Some(toml::Value::String(threshold));
+        }
+
+        if let Ok(backend) = env::var("SPOTIFY_BACKEND") {
+            self.features.fake_spotify = Some(backend == "fake");
+        }
+
+        Ok(())
+    }
+
+    fn validate(self) -> Result<Config, ConfigError> {
+        let features = Features {
+            fake_spotify: self.features.fake_spotify.unwrap_or(false),
+            skip_votes: self.features.skip_votes.unwrap_or(true),
+            webhook: self.features.webhook.unwrap_or(true),
+        };
+
+        let discord_token = self.discord.token
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| invalid("discord.token", "missing, set it or DISCORD_TOKEN"))?;
+
+        let global_commands = self.discord.global_commands.unwrap_or(false);
+
+        // the fake backend never talks to spotify, so doesn't need credentials
+        let required = |value: Option<String>, key: &str, var: &str| match value {
+            Some(value) if !value.is_empty() => Ok(value),
+            _ if features.fake_spotify => Ok(String::new()),
+            _ => Err(invalid(key, format!("missing, set it or {}", var))),
+        };
+
+        let spotify = SpotifyConfig {
+            client_id: required(self.spotify.client_id, "spotify.client_id", "RSPOTIFY_CLIENT_ID")?,
+            client_secret: required(self.spotify.client_secret, "spotify.client_secret", "RSPOTIFY_CLIENT_SECRET")?,
+            redirect_uri: required(self.spotify.redirect_uri, "spotify.redirect_uri", "RSPOTIFY_REDIRECT_URI")?,
+            token_cache: self.spotify.token_cache.unwrap_or_else(|| PathBuf::from(rspotify::DEFAULT_CACHE_PATH)),
+            link_redirect_uri: self.spotify.link_redirect_uri.filter(|uri| !uri.is_empty()),
+            user_token_dir: self.spotify.user_token_dir.unwrap_or_else(|| PathBuf::from("tokens")),
+        };
+
+        if !features.fake_spotify {
+            url::Url::parse(&spotify.redirect_uri)
+                .map_err(|why| invalid("spotify.redirect_uri", why.to_string()))?;
+        }
+
+        if let Some(uri) = &spotify.link_redirect_uri {
+            let uri = url::Url::parse(uri).map_err(|why| invalid("spotify.link_redirect_uri", why.to_string()))?;
+            if uri.port_or_known_default().is_none() {
+                return Err(invalid("spotify.link_redirect_uri", "needs a port to listen on"));
+            }
+        }
+
+        // linked accounts need somewhere for spotify to send people back to
+        let parse_account = |account: Option<String>, key: String, default: AccountMode| {
+            let account = match account {
+                Some(account) => account.parse().map_err(|why| invalid(key.clone(), why))?,
+                None => default,
+            };
+            if account == AccountMode::Linked && (spotify.link_redirect_uri.is_none() || features.fake_spotify) {
+                return Err(invalid(key, "linked accounts need spotify.link_redirect_uri and a real spotify backend"));
+            }
+            Ok(account)
+        };
+
+        let search_results = match self.search.results {
+            Some(results) if !(1..=10).contains(&results) => {
+                return Err(invalid("search.results", "must be between 1 and 10"));
+            }
+            Some(results) => results,
+            None => 5,
+        };
+
+        let queue_max_tracks = match self.queue.max_tracks {
+            Some(0) => return Err(invalid("queue.max_tracks", "must be at least 1")),
+            Some(max_tracks) => max_tracks,
+            None => 500,
+        };
+
+        let poll_interval = match self.playback.poll_secs {
+            Some(0) => return Err(invalid("playback.poll_secs", "must be at least 1")),
+            Some(secs) => Duration::from_secs(secs),
+            None => Duration::from_secs(5),
+        };
+
+        let skip_vote_threshold = match self.skip_vote.threshold {
+            _ if !features.skip_votes => VoteThreshold::Off,
+            Some(threshold) => parse_threshold(threshold, "skip_vote.threshold")?,
+            None => VoteThreshold::Fraction(0.5),
+        };
+
+        let defaults = GuildSettings {
+            default_device: self.spotify.default_device.filter(|name| !name.is_empty()),
+            allowed_channels: vec![],
+            dj_role: None,
+            allowed_users: vec![],
+            skip_vote_threshold,
+            account: parse_account(self.spotify.account, "spotify.account".to_string(), AccountMode::Shared)?,
+        };
+
+        let mut guilds = HashMap::new();
+        for (id, guild) in self.guilds {
+            let key = |field: &str| format!("guilds.{}.{}", id, field);
+
+            let guild_id = id
+                .parse()
+                .map(GuildId)
+                .map_err(|_| invalid(format!("guilds.{}", id), "table name must be a guild id"))?;
+
+            let skip_vote_threshold = match guild.skip_vote_threshold {
+                _ if !features.skip_votes => VoteThreshold::Off,
+                Some(threshold) => parse_threshold(threshold, key("skip_vote_threshold"))?,
+                None => defaults.skip_vote_threshold,
+            };
+
+            guilds.insert(guild_id, GuildSettings {
+                default_device: guild.default_device.or_else(|| defaults.default_device.clone()),
+                allowed_channels: guild.allowed_channels.into_iter().map(ChannelId).collect(),
+                dj_role: guild.dj_role.map(RoleId),
+                allowed_users: guild.allowed_users.into_iter().map(UserId).collect(),
+                skip_vote_threshold,
+                account: parse_account(guild.account, key("account"), defaults.account)?,
+            });
+        }
+
+        // global commands already reach every guild, registering them in a
+        // guild as well would show each one twice there
+        let mut guild_ids: Vec<GuildId> = vec![];
+        if !global_commands {
+            guild_ids.extend(self.discord.guild_ids.into_iter().map(GuildId));
+            for guild_id in guilds.keys() {
+                if !guild_ids.contains(guild_id) {
+                    guild_ids.push(*guild_id);
+                }
+            }
+        }
+
+        if guild_ids.is_empty() && !global_commands {
+            return Err(invalid("discord.guild_ids", "needs at least one guild unless discord.global_commands is on, set it or GUILD_ID"));
+        }
+
+        let webhook = match (features.webhook, self.webhook.port, self.webhook.secret) {
+            (true, Some(port), Some(secret)) if !secret.is_empty() => Some(WebhookConfig { port, secret }),
+            (true, Some(_), _) => {
+                return Err(invalid("webhook.secret", "required when webhook.port is set, set it or WEBHOOK_SECRET"));
+            }
+            _ => None,
+        };
+
+        Ok(Config {
+            discord_token,
+            guild_ids,
+            global_commands,
+            admin_user: self.discord.admin_user_id.map(UserId),
+            spotify,
+            search_results,
+            queue_max_tracks,
+            poll_interval,
+            webhook,
+            features,
+            defaults,
+            guilds,
+        })
+    }
+}
+
+// A vote threshold can be written as a string or a bare number
+fn parse_threshold(value: toml::Value, key: impl Into<String>) -> Result<VoteThreshold, ConfigError> {
+    let threshold = match value {
+        toml::Value::String(threshold) => threshold,
+        toml::Value::Integer(count) => count.to_string(),
+        toml::Value::Float(fraction) => fraction.to_string(),
+        other => {
+            return Err(invalid(key, format!("expected a string or number, found {}", other.type_str())));
+        }
+    };
+
+    VoteThreshold::parse(&threshold).map_err(|why| invalid(key, why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = r#"
+[discord]
+token = "token"
+guild_ids = [1]
+
+[spotify]
+client_id = "id"
+client_secret = "secret"
+redirect_uri = "http://localhost:8888/callback"
+"#;
+
+    fn parse(toml: &str) -> Result<Config, ConfigError> {
+        let raw: RawConfig = toml::from_str(toml).map_err(|why| ConfigError::Parse(PathBuf::from("test.toml"), why))?;
+        raw.validate()
+    }
+
+    fn with(extra: &str) -> Result<Config, ConfigError> {
+        parse(&format!("{}\n{}", MINIMAL, extra))
+    }
+
+    // the key a config was rejected for
+    fn rejected_key(result: Result<Config, ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            other => panic!("expected an invalid key, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        let config = parse(MINIMAL).unwrap();
+
+        assert_eq!(config.guild_ids, [GuildId(1)]);
+        assert_eq!(config.search_results, 5);
+        assert_eq!(config.queue_max_tracks, 500);
+        assert_eq!(config.poll_interval, Duration::from_secs(5));
+        assert_eq!(config.defaults.skip_vote_threshold, VoteThreshold::Fraction(0.5));
+        assert_eq!(config.defaults.account, AccountMode::Shared);
+        assert!(config.webhook.is_none());
+    }
+
+    #[test]
+    fn checks_ranges() {
+        assert_eq!(rejected_key(with("[search]\nresults = 0")), "search.results");
+        assert_eq!(rejected_key(with("[search]\nresults = 11")), "search.results");
+        assert_eq!(with("[search]\nresults = 10").unwrap().search_results, 10);
+
+        assert_eq!(rejected_key(with("[queue]\nmax_tracks = 0")), "queue.max_tracks");
+        assert_eq!(with("[queue]\nmax_tracks = 1").unwrap().queue_max_tracks, 1);
+
+        assert_eq!(rejected_key(with("[playback]\npoll_secs = 0")), "playback.poll_secs");
+        assert_eq!(rejected_key(with("[skip_vote]\nthreshold = \"lots\"")), "skip_vote.threshold");
+    }
+
+    #[test]
+    fn needs_a_token_and_somewhere_to_register() {
+        assert_eq!(rejected_key(parse("[discord]\nguild_ids = [1]")), "discord.token");
+        assert_eq!(rejected_key(parse("[discord]\ntoken = \"token\"\n[features]\nfake_spotify = true")), "discord.guild_ids");
+
+        let global = parse("[discord]\ntoken = \"token\"\nglobal_commands = true\n[features]\nfake_spotify = true").unwrap();
+        assert!(global.guild_ids.is_empty());
+    }
+
+    #[test]
+    fn only_real_spotify_needs_credentials() {
+        let toml = "[discord]\ntoken = \"token\"\nguild_ids = [1]";
+
+        assert_eq!(rejected_key(parse(toml)), "spotify.client_id");
+        assert!(parse(&format!("{}\n[features]\nfake_spotify = true", toml)).is_ok());
+    }
+
+    #[test]
+    fn guilds_fall_back_to_the_defaults() {
+        let config = with("[guilds.42]\ndj_role = 7\n\n[skip_vote]\nthreshold = 3").unwrap();
+
+        let guild = config.guild(Some(GuildId(42)));
+        assert_eq!(guild.dj_role, Some(RoleId(7)));
+        assert_eq!(guild.skip_vote_threshold, VoteThreshold::Count(3));
+        assert!(config.guild_ids.contains(&GuildId(42)));
+
+        // unknown guilds and DMs get the defaults
+        assert_eq!(config.guild(Some(GuildId(5))).dj_role, None);
+        assert_eq!(config.guild(None).dj_role, None);
+
+        assert_eq!(rejected_key(with("[guilds.music]\ndj_role = 7")), "guilds.music");
+    }
+
+    #[test]
+    fn global_commands_are_not_registered_per_guild() {
+        let config = with("[guilds.42]\ndj_role = 7").unwrap();
+        assert_eq!(config.guild_ids, [GuildId(1), GuildId(42)]);
+
+        let toml = MINIMAL.replace("guild_ids = [1]", "guild_ids = [1]\nglobal_commands = true");
+        let config = parse(&format!("{}\n[guilds.42]\ndj_role = 7", toml)).unwrap();
+
+        assert!(config.global_commands);
+        assert!(config.guild_ids.is_empty());
+        // the guild's settings still apply
+        assert_eq!(config.guild(Some(GuildId(42))).dj_role, Some(RoleId(7)));
+    }
+
+    #[test]
+    fn linked_accounts_and_webhooks_need_their_settings() {
+        assert_eq!(rejected_key(with("[guilds.42]\naccount = \"linked\"")), "guilds.42.account");
+        assert_eq!(rejected_key(with("[webhook]\nport = 8080")), "webhook.secret");
+
+        let config = with("[webhook]\nport = 8080\nsecret = \"hush\"").unwrap();
+        assert_eq!(config.webhook.unwrap().port, 8080);
+    }
+
+    #[test]
+    fn rejects_bad_toml() {
+        assert!(matches!(parse("[discord\ntoken = 1"), Err(ConfigError::Parse(..))));
+        // typos are caught rather than ignored
+        assert!(matches!(with("[search]\nresult = 3"), Err(ConfigError::Parse(..))));
+        assert!(matches!(with("[queue]\nmax_tracks = \"lots\""), Err(ConfigError::Parse(..))));
+    }
+
+    // the only test touching the environment, so nothing else sees these
+    #[test]
+    fn environment_overrides_the_file() {
+        let mut raw: RawConfig = toml::from_str(MINIMAL).unwrap();
+
+        env::set_var("QUEUE_MAX_TRACKS", "25");
+        env::set_var("GUILD_ID", "2, 3");
+        env::set_var("SKIP_VOTE_THRESHOLD", "off");
+        let applied = raw.apply_env();
+        env::remove_var("QUEUE_MAX_TRACKS");
+        env::remove_var("GUILD_ID");
+        env::remove_var("SKIP_VOTE_THRESHOLD");
+
+        applied.unwrap();
+        let config = raw.validate().unwrap();
+        assert_eq!(config.queue_max_tracks, 25);
+        assert_eq!(config.guild_ids, [GuildId(2), GuildId(3)]);
+        assert_eq!(config.defaults.skip_vote_threshold, VoteThreshold::Off);
+
+        let mut raw: RawConfig = toml::from_str(MINIMAL).unwrap();
+        env::set_var("SEARCH_RESULTS", "many");
+        let applied = raw.apply_env();
+        env::remove_var("SEARCH_RESULTS");
+
+        match applied {
+            Err(ConfigError::Invalid { key, reason }) => {
+                assert_eq!(key, "search.results");
+                assert!(reason.contains("SEARCH_RESULTS"));
+            }
+            other => panic!("expected a bad override, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serenity::builder::CreateEmbed;
+use serenity::http::Http;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, MessageId};
+use tokio::sync::Mutex;
+
+use crate::backend::SpotifyBackend;
+use crate::commands::nowplaying;
+use crate::commands::nowplaying::ContextCache;
+use crate::playback::PlaybackState;
+use crate::response::state_colour;
+
+// discord rate limits message edits, so don't edit more often than this
+const MIN_EDIT_INTERVAL: Duration = Duration::from_secs(5);
+
+// stop updating once nothing has played for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// Now playing messages kept up to date from the playback poller. Each channel
+// has at most one, a newer one supersedes the last.
+pub struct LiveMessages {
+    current: Mutex<HashMap<ChannelId, MessageId>>,
+}
+
+impl LiveMessages {
+    pub fn new() -> Self {
+        LiveMessages {
+            current: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn start(
+        self: &Arc<Self>,
+        http: Arc<Http>,
+        message: Message,
+        spotify: Arc<dyn SpotifyBackend>,
+        state: PlaybackState,
+    ) {
+        self.current.lock().await.insert(message.channel_id, message.id);
+
+        let live = self.clone();
+        tokio::spawn(async move {
+            live.update(http, message, spotify, state).await;
+        });
+    }
+
+    async fn is_current(&self, message: &Message) -> bool {
+        self.current.lock().await.get(&message.channel_id) == Some(&message.id)
+    }
+
+    async fn update(
+        &self,
+        http: Arc<Http>,
+        mut message: Message,
+        spotify: Arc<dyn SpotifyBackend>,
+        mut state: PlaybackState,
+    ) {
+        info!("Live now playing started in {}", message.channel_id);
+
+        let mut cache: ContextCache = None;
+        let mut last_active = Instant::now();
+        let mut last_embed: Option<CreateEmbed> = None;
+
+        loop {
+            // wait for the next poll, which can have come in while sleeping
+            match tokio::time::timeout(IDLE_TIMEOUT, state.changed()).await {
+                Ok(Ok(())) => (),
+                // timed out, or the poller stopped
+                _ => break,
+            }
+
+            if !self.is_current(&message).await {
+                break;
+            }
+
+            let playback = state.borrow().clone();
+
+            if matches!(&playback, Some(playback) if playback.is_playing) {
+                last_active = Instant::now();
+            } else if last_active.elapsed() >= IDLE_TIMEOUT {
+                break;
+            }
+
+            let embed = match &playback {
+                Some(playback) => match nowplaying::embed(spotify.as_ref(), playback, &mut cache).await {
+                    Ok(embed) => embed,
+                    Err(_) => nothing_playing(),
+                },
+                None => nothing_playing(),
+            };
+
+            if let Err(why) = message.edit(&http, |edit| edit.set_embed(embed.clone())).await {
+                // most likely the message was deleted
+                warn!("Cannot update live now playing: {}", why);
+                self.finish(&message).await;
+                return;
+            }
+
+            last_embed = Some(embed);
+
+            tokio::time::sleep(MIN_EDIT_INTERVAL).await;
+        }
+
+        // say it has stopped, so nobody trusts a stale message
+        if let Some(mut embed) = last_embed {
+            embed.footer(|footer| footer.text("No longer updating"));
+            if let Err(why) = message.edit(&http, |edit| edit.set_embed(embed)).await {
+                warn!("Cannot update live now playing: {}", why);
+            }
+        }
+
+        self.finish(&message).await;
+        info!("Live now playing stopped in {}", message.channel_id);
+    }
+
+    async fn finish(&self, message: &Message) {
+        let mut current = self.current.lock().await;
+        if current.get(&message.channel_id) == Some(&message.id) {
+            current.remove(&message.channel_id);
+        }
+    }
+}
+
+fn nothing_playing() -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed.title("Nothing playing").colour(state_colour(false));
+    embed
+}
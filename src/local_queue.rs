@@ -0,0 +1,299 @@
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::{error, info};
+use rand::seq::SliceRandom;
+use serenity::model::id::UserId;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use rspotify::{
+    model::enums::misc::RepeatState,
+    model::idtypes::TrackId,
+    model::track::FullTrack,
+    model::track::SimplifiedTrack,
+    prelude::PlayableId,
+};
+
+use crate::CommandError;
+use crate::backend::SpotifyBackend;
+use crate::playback::PlaybackEvent;
+
+// A track waiting to be played by the bot, and who asked for it
+#[derive(Debug, Clone)]
+pub struct QueueEntry {
+    pub id: TrackId<'static>,
+    pub name: String,
+    pub artist: String,
+    pub duration: Duration,
+    pub requested_by: UserId,
+}
+
+impl QueueEntry {
+    pub fn from_track(track: &FullTrack, requested_by: UserId) -> Option<Self> {
+        Some(QueueEntry {
+            id: track.id.clone()?,
+            name: track.name.clone(),
+            artist: track.artists.first().map(|artist| artist.name.clone()).unwrap_or_default(),
+            duration: track.duration,
+            requested_by,
+        })
+    }
+
+    pub fn from_simplified(track: &SimplifiedTrack, requested_by: UserId) -> Option<Self> {
+        Some(QueueEntry {
+            id: track.id.clone()?,
+            name: track.name.clone(),
+            artist: track.artists.first().map(|artist| artist.name.clone()).unwrap_or_default(),
+            duration: track.duration,
+            requested_by,
+        })
+    }
+}
+
+// Pending tracks owned by the bot rather than spotify, so they can be
+// inspected and changed after being queued. Spotify only ever sees the track
+// that is currently playing.
+#[derive(Debug, Default)]
+pub struct LocalQueue {
+    entries: VecDeque<QueueEntry>,
+}
+
+impl LocalQueue {
+    pub fn new() -> Self {
+        LocalQueue { entries: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, entry: QueueEntry) {
+        self.entries.push_back(entry);
+    }
+
+    pub fn pop(&mut self) -> Option<QueueEntry> {
+        self.entries.pop_front()
+    }
+
+    pub fn entries(&self) -> &VecDeque<QueueEntry> {
+        &self.entries
+    }
+
+    pub fn get(&self, index: usize) -> Option<&QueueEntry> {
+        self.entries.get(index)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<QueueEntry> {
+        self.entries.remove(index)
+    }
+
+    // Move the entry at `from` so it ends up at `to`. False if either is out
+    // of range.
+    pub fn move_entry(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.entries.len() || to >= self.entries.len() {
+            return false;
+        }
+        if let Some(entry) = self.entries.remove(from) {
+            self.entries.insert(to, entry);
+        }
+        true
+    }
+
+    // Returns how many entries were dropped
+    pub fn clear(&mut self) -> usize {
+        let count = self.entries.len();
+        self.entries.clear();
+        count
+    }
+
+    pub fn shuffle(&mut self) {
+        self.entries.make_contiguous().shuffle(&mut rand::thread_rng());
+    }
+
+    // Drop entries for tracks queued earlier, keeping the first of each.
+    // Returns how many were dropped.
+    pub fn dedupe(&mut self) -> usize {
+        let before = self.entries.len();
+        let mut seen = HashSet::new();
+        self.entries.retain(|entry| seen.insert(entry.id.clone()));
+        before - self.entries.len()
+    }
+}
+
+// Start playing the next pending entry. Returns the entry that was started, or
+// None if the queue was empty.
+pub async fn play_next<S: SpotifyBackend + ?Sized>(
+    queue: &Mutex<LocalQueue>,
+    spotify: &S,
+) -> Result<Option<QueueEntry>, CommandError> {
+    let entry = match queue.lock().await.pop() {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+
+    spotify.start_uris_playback(vec![PlayableId::Track(entry.id.clone())], None).await?;
+    info!("Playing queued track {} requested by {}", entry.name, entry.requested_by);
+
+    Ok(Some(entry))
+}
+
+// Whether spotify is idle, meaning a newly queued track can start right away.
+// A paused track still counts as playing, starting another would lose its place.
+pub async fn is_idle<S: SpotifyBackend + ?Sized>(spotify: &S) -> Result<bool, CommandError> {
+    Ok(match spotify.current_playback().await? {
+        Some(playback) => playback.item.is_none(),
+        None => true,
+    })
+}
+
+async fn repeats_track<S: SpotifyBackend + ?Sized>(spotify: &S) -> Result<bool, CommandError> {
+    Ok(match spotify.current_playback().await? {
+        Some(playback) => playback.repeat_state == RepeatState::Track,
+        None => false,
+    })
+}
+
+// The poller and the spotifyd webhook can both report the same track ending
+const DUPLICATE_END_WINDOW: Duration = Duration::from_secs(10);
+
+// Whether `id` ending at `now` is the end reported just before, coming in again
+fn is_duplicate_end(last_ended: &Option<(String, Instant)>, id: &str, now: Instant) -> bool {
+    matches!(last_ended, Some((last_id, at)) if last_id == id && now.duration_since(*at) < DUPLICATE_END_WINDOW)
+}
+
+// Start the next pending entry whenever the current track plays to its end
+pub fn spawn_advancer(
+    queue: Arc<Mutex<LocalQueue>>,
+    spotify: Arc<dyn SpotifyBackend>,
+    mut events: broadcast::Receiver<PlaybackEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut last_ended: Option<(String, Instant)> = None;
+
+        loop {
+            match events.recv().await {
+                Ok(PlaybackEvent::TrackEnded { id }) => {
+                    let now = Instant::now();
+                    if is_duplicate_end(&last_ended, &id, now) {
+                        continue;
+                    }
+                    last_ended = Some((id, now));
+
+                    // the track is meant to play again, queued ones can wait
+                    match repeats_track(spotify.as_ref()).await {
+                        Ok(true) => continue,
+                        Ok(false) => (),
+                        Err(why) => error!("Failed to check repeat state: {}", String::from(why)),
+                    }
+
+                    if let Err(why) = play_next(&queue, spotify.as_ref()).await {
+                        error!("Failed to play next queued track: {}", String::from(why));
+                    }
+                }
+                Ok(_) => (),
+                Err(RecvError::Lagged(_)) => (),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FakeSpotify;
+    use crate::backend::fake;
+
+    fn entry(id: &str, name: &str, user: u64) -> QueueEntry {
+        QueueEntry::from_track(&fake::track(id, name, "Someone", 180), UserId(user)).unwrap()
+    }
+
+    fn names(queue: &LocalQueue) -> Vec<&str> {
+        queue.entries().iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn moves_and_dedupes_entries() {
+        let mut queue = LocalQueue::new();
+        queue.push(entry("4uLU6hMCjMI75M1A2tKUQC", "a", 1));
+        queue.push(entry("7GhIk7Il098yCjg4BQjzvb", "b", 1));
+        queue.push(entry("4uLU6hMCjMI75M1A2tKUQC", "a again", 2));
+
+        assert!(queue.move_entry(2, 0));
+        assert_eq!(names(&queue), ["a again", "a", "b"]);
+        assert!(!queue.move_entry(0, 3));
+
+        assert_eq!(queue.dedupe(), 1);
+        assert_eq!(names(&queue), ["a again", "b"]);
+        assert_eq!(queue.clear(), 2);
+    }
+
+    #[tokio::test]
+    async fn only_track_repeat_holds_the_queue() {
+        let spotify = Arc::new(FakeSpotify::playing_demo_album().await);
+        assert!(!repeats_track(spotify.as_ref()).await.unwrap());
+
+        spotify.state().repeat_state = RepeatState::Context;
+        assert!(!repeats_track(spotify.as_ref()).await.unwrap());
+
+        spotify.state().repeat_state = RepeatState::Track;
+        assert!(repeats_track(spotify.as_ref()).await.unwrap());
+
+        // nothing playing, so nothing repeating
+        spotify.state().devices[0].is_active = false;
+        assert!(!repeats_track(spotify.as_ref()).await.unwrap());
+    }
+
+    #[test]
+    fn the_same_end_twice_is_a_duplicate() {
+        let now = Instant::now();
+        let last_ended = Some(("abc".to_string(), now));
+
+        assert!(is_duplicate_end(&last_ended, "abc", now + Duration::from_secs(3)));
+        assert!(!is_duplicate_end(&last_ended, "def", now + Duration::from_secs(3)));
+        assert!(!is_duplicate_end(&last_ended, "abc", now + DUPLICATE_END_WINDOW));
+        assert!(!is_duplicate_end(&None, "abc", now));
+    }
+
+    async fn advance(spotify: Arc<FakeSpotify>, queue: Arc<Mutex<LocalQueue>>, ended: &[&str]) {
+        let events = broadcast::channel(8).0;
+        let advancer = spawn_advancer(queue, spotify, events.subscribe());
+
+        for id in ended {
+            events.send(PlaybackEvent::TrackEnded { id: id.to_string() }).unwrap();
+        }
+
+        // closing the channel lets the advancer finish what it was sent
+        drop(events);
+        advancer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn plays_the_next_entry_once_per_end() {
+        let spotify = Arc::new(FakeSpotify::playing_demo_album().await);
+        let queue = Arc::new(Mutex::new(LocalQueue::new()));
+        queue.lock().await.push(entry("0b11D9D0hMOYCIMN3OKreM", "Blue (Da Ba Dee)", 1));
+        queue.lock().await.push(entry("7GhIk7Il098yCjg4BQjzvb", "Together Forever", 1));
+
+        // the poller and the webhook both report the end
+        advance(spotify.clone(), queue.clone(), &["4uLU6hMCjMI75M1A2tKUQC", "4uLU6hMCjMI75M1A2tKUQC"]).await;
+
+        assert_eq!(spotify.state().item.as_ref().unwrap().name, "Blue (Da Ba Dee)");
+        assert_eq!(names(&*queue.lock().await), ["Together Forever"]);
+    }
+
+    #[tokio::test]
+    async fn waits_while_the_track_repeats() {
+        let spotify = Arc::new(FakeSpotify::playing_demo_album().await);
+        spotify.state().repeat_state = RepeatState::Track;
+        let queue = Arc::new(Mutex::new(LocalQueue::new()));
+        queue.lock().await.push(entry("0b11D9D0hMOYCIMN3OKreM", "Blue (Da Ba Dee)", 1));
+
+        advance(spotify.clone(), queue.clone(), &["4uLU6hMCjMI75M1A2tKUQC"]).await;
+
+        assert_eq!(spotify.state().item.as_ref().unwrap().name, "Never Gonna Give You Up");
+        assert_eq!(names(&*queue.lock().await), ["Blue (Da Ba Dee)"]);
+    }
+}
@@ -1,16 +1,39 @@
 mod commands;
+mod backend;
+mod local_queue;
+mod playback;
+mod webhook;
+mod vote;
+mod playing_context;
+mod auth;
+mod config;
+mod accounts;
+mod permissions;
+mod autocomplete;
+mod response;
+mod live;
 
-use std::env;
+use std::sync::Arc;
+use std::time::Duration;
 
 use dotenv;
 
 use serenity::async_trait;
 use serenity::model::application::interaction::{Interaction, InteractionResponseType};
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
 use serenity::model::gateway::Ready;
+use serenity::http::Http;
+use serenity::builder::CreateApplicationCommands;
+use serenity::builder::CreateComponents;
+use serenity::model::application::command::Command;
 use serenity::model::id::GuildId;
+use serenity::model::id::UserId;
 use serenity::prelude::*;
-use log::{Record, Level, Metadata, SetLoggerError, LevelFilter, debug, error, info};
+use log::{Record, Level, Metadata, SetLoggerError, LevelFilter, error, info, warn};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use serenity::model::prelude::interaction::application_command::{
+    ApplicationCommandInteraction,
     CommandDataOption,
     CommandDataOptionValue,
 };
@@ -20,13 +43,26 @@ use rspotify::{
     Config,
     Credentials,
     OAuth,
-    clients::OAuthClient,
     scopes,
     ClientError,
+    http::HttpError,
     model::enums::types::SearchType,
     model::idtypes::IdError,
 };
 
+use crate::backend::SpotifyBackend;
+use crate::backend::FakeSpotify;
+use crate::local_queue::LocalQueue;
+use crate::accounts::Accounts;
+use crate::autocomplete::Autocomplete;
+use crate::response::Response;
+use crate::live::LiveMessages;
+use crate::playback::PlaybackState;
+use crate::config::AccountMode;
+use crate::config::GuildSettings;
+use crate::vote::SkipVotes;
+use crate::vote::VoteThreshold;
+
 struct SimpleLogger;
 
 impl log::Log for SimpleLogger {
@@ -50,10 +86,16 @@ pub fn log_init() -> Result<(), SetLoggerError> {
         .map(|()| log::set_max_level(LevelFilter::Info))
 }
 
-// Custom error type
+// Custom error type. Spotify failures people can act on get a variant of
+// their own, the raw error is kept so it can be logged.
 #[derive(Debug)]
 pub enum CommandError {
     SpotifyError(ClientError),
+    NoActiveDevice(ClientError),
+    RateLimited(Duration, ClientError),
+    AuthExpired(ClientError),
+    NotFound(ClientError),
+    Forbidden(ClientError),
     IdError(IdError),
     SimpleError(String),
 }
@@ -66,7 +108,29 @@ impl From<&str> for CommandError {
 
 impl From<ClientError> for CommandError {
     fn from(error: ClientError) -> Self {
-        CommandError::SpotifyError(error)
+        if let Some(wait) = backend::retry_after(&error) {
+            return CommandError::RateLimited(wait, error);
+        }
+
+        let (status, player) = match &error {
+            ClientError::Http(http) => match http.as_ref() {
+                HttpError::StatusCode(response) => (
+                    Some(response.status().as_u16()),
+                    response.url().path().starts_with("/v1/me/player"),
+                ),
+                _ => (None, false),
+            },
+            _ => (None, false),
+        };
+
+        match status {
+            Some(401) => CommandError::AuthExpired(error),
+            Some(403) => CommandError::Forbidden(error),
+            // the player endpoints 404 when there is nothing to control
+            Some(404) if player => CommandError::NoActiveDevice(error),
+            Some(404) => CommandError::NotFound(error),
+            _ => CommandError::SpotifyError(error),
+        }
     }
 }
 
@@ -76,10 +140,26 @@ impl From<IdError> for CommandError {
     }
 }
 
+// What to tell the user. The details of spotify errors only go to the log.
 impl From<CommandError> for String {
     fn from(command_error: CommandError) -> Self {
+        match &command_error {
+            CommandError::SpotifyError(error)
+            | CommandError::NoActiveDevice(error)
+            | CommandError::RateLimited(_, error)
+            | CommandError::AuthExpired(error)
+            | CommandError::NotFound(error)
+            | CommandError::Forbidden(error) => warn!("Spotify request failed: {:?}", error),
+            _ => (),
+        }
+
         match command_error {
-            CommandError::SpotifyError(error) => format!("Error: {}", error.to_string()),
+            CommandError::SpotifyError(_) => "Error: Something went wrong talking to spotify, try again in a bit".to_string(),
+            CommandError::NoActiveDevice(_) => "Error: No active spotify device, start playing somewhere or use /connect".to_string(),
+            CommandError::RateLimited(wait, _) => format!("Error: Spotify is rate limiting the bot, try again in {}s", wait.as_secs().max(1)),
+            CommandError::AuthExpired(_) => "Error: The spotify login has expired, it needs logging in again (or /link again for your own account)".to_string(),
+            CommandError::NotFound(_) => "Error: Spotify couldn't find that".to_string(),
+            CommandError::Forbidden(_) => "Error: Spotify refused that, controlling playback needs spotify premium".to_string(),
             CommandError::IdError(error) => format!("Error: {}", error.to_string()),
             CommandError::SimpleError(error) => format!("Error: {}", error),
         }
@@ -159,16 +239,362 @@ pub fn bool_from_value(
     }
 }
 
-pub fn id_from_url(url: &str) -> Result<&str, CommandError> {
-    Ok(url.rsplit('/')
-        .collect::<Vec<&str>>()
-        .get(0)
-        .ok_or("Failed to parse url")?
-    )
+// m:ss, or h:mm:ss for anything an hour or longer
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds / (60 * 60) > 0 {
+        format!("{}:{:0>2}:{:0>2}", seconds / 60 / 60, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{}:{:0>2}", seconds / 60, seconds % 60)
+    }
+}
+
+const LINK_TYPES: [&str; 4] = ["track", "album", "playlist", "artist"];
+
+// The spotify uri a link points at, or None if `link` isn't one. Takes
+// open.spotify.com urls (with or without a locale prefix or query string),
+// spotify: uris, and bare ids, which are taken to be of `bare_type`.
+pub fn uri_from_link(link: &str, bare_type: SearchType) -> Option<String> {
+    let link = link.trim();
+
+    if let Some(rest) = link.strip_prefix("spotify:") {
+        // old playlist uris look like spotify:user:<name>:playlist:<id>
+        let mut parts = rest.rsplit(':');
+        let id = parts.next()?;
+        uri_from_parts(parts.next()?, id)
+    } else if link.contains("open.spotify.com") {
+        let with_scheme = if link.contains("://") { link.to_string() } else { format!("https://{}", link) };
+        let url = url::Url::parse(&with_scheme).ok()?;
+        if url.host_str() != Some("open.spotify.com") {
+            return None;
+        }
+
+        // skips prefixes like /intl-de/ or /embed/ by looking for the type
+        let segments: Vec<&str> = url.path_segments()?.filter(|segment| !segment.is_empty()).collect();
+        let position = segments.iter().position(|segment| LINK_TYPES.contains(segment))?;
+        uri_from_parts(segments[position], segments.get(position + 1)?)
+    } else if is_spotify_id(link) {
+        let kind = match bare_type {
+            SearchType::Track => "track",
+            SearchType::Album => "album",
+            SearchType::Playlist => "playlist",
+            SearchType::Artist => "artist",
+            _ => return None,
+        };
+        uri_from_parts(kind, link)
+    } else {
+        None
+    }
+}
+
+fn uri_from_parts(kind: &str, id: &str) -> Option<String> {
+    if LINK_TYPES.contains(&kind) && is_spotify_id(id) {
+        Some(format!("spotify:{}:{}", kind, id))
+    } else {
+        None
+    }
+}
+
+// spotify ids are 22 characters of base 62
+fn is_spotify_id(id: &str) -> bool {
+    id.len() == 22 && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn register_commands(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands
+        .create_application_command(|command| commands::search::register(command))
+        .create_application_command(|command| commands::play::register(command))
+        .create_application_command(|command| commands::queue::register(command))
+        .create_application_command(|command| commands::set::register(command))
+        .create_application_command(|command| commands::skip::register(command))
+        .create_application_command(|command| commands::list::register(command))
+        // /pause and /resume predate /playback, kept for anyone used to them
+        .create_application_command(|command| commands::pause::register(command))
+        .create_application_command(|command| commands::resume::register(command))
+        .create_application_command(|command| commands::playback::register(command))
+        .create_application_command(|command| commands::previous::register(command))
+        .create_application_command(|command| commands::seek::register(command))
+        .create_application_command(|command| commands::connect::register(command))
+        .create_application_command(|command| commands::status::register(command))
+        .create_application_command(|command| commands::nowplaying::register(command))
+        .create_application_command(|command| commands::info::register(command))
+        .create_application_command(|command| commands::devices::register(command))
+        .create_application_command(|command| commands::link::register(command))
+        .create_application_command(|command| commands::unlink::register(command))
+}
+
+// discord rate limits edits, so progress is shown at most this often
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+// Show each progress update on the deferred response to `command`, until
+// the sender is dropped
+fn spawn_progress_edits(
+    http: Arc<Http>,
+    command: ApplicationCommandInteraction,
+    mut updates: watch::Receiver<String>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while updates.changed().await.is_ok() {
+            let update = updates.borrow().clone();
+
+            if let Err(why) = command.edit_original_interaction_response(&http, |edit| edit.content(update)).await {
+                warn!("Cannot show progress: {}", why);
+            }
+
+            tokio::time::sleep(PROGRESS_INTERVAL).await;
+        }
+    })
 }
 
 struct Handler {
-    spotify: AuthCodeSpotify,
+    spotify: Arc<dyn SpotifyBackend>,
+    queue: Arc<Mutex<LocalQueue>>,
+    votes: Arc<Mutex<SkipVotes>>,
+    config: Arc<config::Config>,
+    accounts: Option<Arc<Accounts>>,
+    autocomplete: Autocomplete,
+    playback: PlaybackState,
+    live: Arc<LiveMessages>,
+}
+
+impl Handler {
+    async fn run_command(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+        settings: &GuildSettings,
+        progress: Option<&commands::queue::Progress>,
+    ) -> Result<Response, CommandError> {
+        let full_name = commands::full_name(&command.data.name, &command.data.options);
+        let (account, queue) = self.account_for(settings, &full_name, command.user.id).await?;
+
+        // DJs skip straight away instead of voting
+        let vote_threshold = if permissions::is_dj(settings, command.user.id, command.member.as_ref()) {
+            VoteThreshold::Off
+        } else {
+            settings.skip_vote_threshold
+        };
+
+        let listeners = command.guild_id
+            .and_then(|guild_id| vote::voice_listeners(&ctx.cache, guild_id, command.user.id));
+
+        let response = match command.data.name.as_str() {
+            "search" => commands::search::run(&command.data.options, account.as_ref(), self.config.search_results).await?,
+            "play" => commands::play::run(&command.data.options, account.as_ref()).await?.into(),
+            "queue" => commands::queue::run(
+                &command.data.options,
+                account.as_ref(),
+                queue,
+                command.user.id,
+                self.config.queue_max_tracks,
+                progress,
+                permissions::may_manage(settings, command.user.id, command.member.as_ref()),
+            ).await?.into(),
+            "set" => commands::set::run(&command.data.options, self.spotify.as_ref()).await?.into(),
+            "skip" => commands::skip::run(
+                &command.data.options,
+                self.spotify.as_ref(),
+                &self.queue,
+                &self.votes,
+                vote_threshold,
+                command.user.id,
+                listeners,
+            ).await?.into(),
+            "list" => commands::list::run(&command.data.options, self.spotify.as_ref(), &self.queue).await?,
+            "pause" => commands::pause::run(&command.data.options, self.spotify.as_ref()).await?.into(),
+            "resume" => commands::resume::run(&command.data.options, self.spotify.as_ref()).await?.into(),
+            "playback" => commands::playback::run(&command.data.options, self.spotify.as_ref()).await?.into(),
+            "previous" => commands::previous::run(&command.data.options, self.spotify.as_ref()).await?.into(),
+            "seek" => commands::seek::run(&command.data.options, self.spotify.as_ref()).await?.into(),
+            "connect" => commands::connect::run(&command.data.options, self.spotify.as_ref(), settings.default_device.as_deref()).await?.into(),
+            "status" => commands::status::run(&command.data.options, account.as_ref()).await?,
+            "nowplaying" => commands::nowplaying::run(&command.data.options, self.spotify.as_ref()).await?,
+            "info" => commands::info::run(&command.data.options, self.spotify.as_ref()).await?.into(),
+            "devices" => commands::devices::run(&command.data.options, self.spotify.as_ref()).await?.into(),
+            "link" => commands::link::run(&command.data.options, self.accounts.as_deref(), command.user.id).await?.into(),
+            "unlink" => commands::unlink::run(&command.data.options, self.accounts.as_deref(), command.user.id).await?.into(),
+            _ => return Err(CommandError::SimpleError("not implemented :(".to_string())),
+        };
+
+        Ok(response)
+    }
+
+    // Run `command` and reply to it. Slow commands are acknowledged straight
+    // away and show what they send to their progress channel until the real
+    // reply replaces it. Text too long for one message continues in follow-ups.
+    async fn respond(
+        &self,
+        ctx: &Context,
+        command: &ApplicationCommandInteraction,
+        settings: &GuildSettings,
+    ) {
+        let name = command.data.name.as_str();
+
+        // login links are personal
+        let ephemeral = name == "link";
+
+        let deferred = commands::is_slow(name);
+
+        if deferred {
+            if let Err(why) = command
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                        .interaction_response_data(|message| message.ephemeral(ephemeral))
+                })
+                .await
+            {
+                error!("Cannot defer slash command: {}", why);
+                return;
+            }
+        }
+
+        let (progress, updates) = watch::channel(String::new());
+        let edits = deferred.then(|| spawn_progress_edits(ctx.http.clone(), command.clone(), updates));
+
+        let content_result = self.run_command(ctx, command, settings, Some(&progress)).await;
+
+        // stop showing progress so it can't overwrite the reply
+        if let Some(edits) = edits {
+            edits.abort();
+            let _ = edits.await;
+        }
+
+        let live = name == "nowplaying"
+            && commands::nowplaying::is_live(&command.data.options)
+            && content_result.is_ok();
+
+        let mut reply = match content_result {
+            Ok(reply) => reply,
+            Err(why) => {
+                // errors only go to whoever ran the command
+                let content = String::from(why);
+                let sent = if deferred {
+                    // a deferred reply can't be made ephemeral, so swap it for a follow-up
+                    if let Err(why) = command.delete_original_interaction_response(&ctx.http).await {
+                        error!("Cannot delete deferred response: {}", why);
+                    }
+                    command
+                        .create_followup_message(&ctx.http, |message| message.ephemeral(true).content(content))
+                        .await
+                        .map(|_| ())
+                } else {
+                    command
+                        .create_interaction_response(&ctx.http, |response| {
+                            response
+                                .kind(InteractionResponseType::ChannelMessageWithSource)
+                                .interaction_response_data(|message| message.ephemeral(true).content(content))
+                        })
+                        .await
+                };
+                if let Err(why) = sent {
+                    error!("Cannot respond to slash command: {}", why);
+                }
+                return;
+            }
+        };
+
+        // let others join in on an open skip vote
+        if name == "skip" && self.votes.lock().await.is_open() {
+            let mut components = CreateComponents::default();
+            commands::skip::vote_button(&mut components);
+            reply.components = Some(components);
+        }
+
+        let overflow = reply.split_overflow();
+
+        let sent = if deferred {
+            command
+                .edit_original_interaction_response(&ctx.http, |edit| reply.render_edit(edit))
+                .await
+                .map(|_| ())
+        } else {
+            command
+                .create_interaction_response(&ctx.http, |response| {
+                    response
+                        .kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|message| reply.render(message).ephemeral(ephemeral))
+                })
+                .await
+        };
+
+        if let Err(why) = sent {
+            error!("Cannot respond to slash command: {}", why);
+            return;
+        }
+
+        for content in overflow {
+            if let Err(why) = command
+                .create_followup_message(&ctx.http, |message| message.ephemeral(ephemeral).content(content))
+                .await
+            {
+                error!("Cannot send follow-up message: {}", why);
+                break;
+            }
+        }
+
+        if live {
+            match command.get_interaction_response(&ctx.http).await {
+                Ok(message) => {
+                    self.live.start(ctx.http.clone(), message, self.spotify.clone(), self.playback.clone()).await;
+                }
+                Err(why) => error!("Cannot find live now playing message: {}", why),
+            }
+        }
+    }
+
+    // The account `name` should act on for `user`, and the local queue to use
+    // with it. In linked guilds some commands act on the account of whoever ran
+    // them, and queue onto that account's own queue.
+    async fn account_for(
+        &self,
+        settings: &GuildSettings,
+        name: &str,
+        user: UserId,
+    ) -> Result<(Arc<dyn SpotifyBackend>, Option<&Mutex<LocalQueue>>), CommandError> {
+        match settings.account {
+            AccountMode::Linked if accounts::uses_linked_account(name) => Ok((self.linked_account(user).await?, None)),
+            _ => Ok((self.spotify.clone(), Some(self.queue.as_ref()))),
+        }
+    }
+
+    // Play or queue the result picked from a search message
+    async fn run_search_action(
+        &self,
+        component: &MessageComponentInteraction,
+        settings: &GuildSettings,
+        action: commands::search::Action,
+        uri: &str,
+    ) -> Result<String, CommandError> {
+        let name = match action {
+            commands::search::Action::Play => "play",
+            commands::search::Action::Queue => "queue add",
+        };
+
+        let (account, queue) = self.account_for(settings, name, component.user.id).await?;
+
+        let label = commands::search::choices_from_message(&component.message.components)
+            .into_iter()
+            .find(|choice| choice.uri == uri)
+            .map(|choice| choice.label)
+            .unwrap_or_else(|| uri.to_string());
+
+        match action {
+            commands::search::Action::Play => commands::play::play_uri(account.as_ref(), uri, &label).await,
+            commands::search::Action::Queue => {
+                commands::queue::queue_uri(account.as_ref(), queue, uri, component.user.id, self.config.queue_max_tracks, None).await
+            }
+        }
+    }
+
+    async fn linked_account(&self, user: UserId) -> Result<Arc<dyn SpotifyBackend>, CommandError> {
+        let accounts = self.accounts.as_ref().ok_or("Account linking is not set up on this bot")?;
+
+        match accounts.get(user).await? {
+            Some(spotify) => Ok(spotify),
+            None => Err(CommandError::from("Link your spotify account with /link first")),
+        }
+    }
 }
 
 #[async_trait]
@@ -177,36 +603,328 @@ impl EventHandler for Handler {
         if let Interaction::ApplicationCommand(command) = interaction {
             info!("Received command interaction: {:#?}", command);
 
-            let content_result = match command.data.name.as_str() {
-                "search" => commands::search::run(&command.data.options, &self.spotify).await,
-                "play" => commands::play::run(&command.data.options, &self.spotify).await,
-                "queue" => commands::queue::run(&command.data.options, &self.spotify).await,
-                "set" => commands::set::run(&command.data.options, &self.spotify).await,
-                "skip" => commands::skip::run(&command.data.options, &self.spotify).await,
-                "list" => commands::list::run(&command.data.options, &self.spotify).await,
-                "pause" => commands::pause::run(&command.data.options, &self.spotify).await,
-                "resume" => commands::resume::run(&command.data.options, &self.spotify).await,
-                "connect" => commands::connect::run(&command.data.options, &self.spotify).await,
-                "status" => commands::status::run(&command.data.options, &self.spotify).await,
-                "info" => commands::info::run(&command.data.options, &self.spotify).await,
-                "devices" => commands::devices::run(&command.data.options, &self.spotify).await,
+            let settings = self.config.guild(command.guild_id);
+
+            let allowed = permissions::check_in(
+                settings,
+                command.channel_id,
+                &commands::full_name(&command.data.name, &command.data.options),
+                command.user.id,
+                command.member.as_ref(),
+            );
+
+            if let Err(why) = allowed {
+                if let Err(why) = command
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::ChannelMessageWithSource)
+                            .interaction_response_data(|message| {
+                                message.ephemeral(true).content(String::from(why))
+                            })
+                    })
+                    .await
+                {
+                    error!("Cannot respond to slash command: {}", why);
+                }
+                return;
+            }
+
+            self.respond(&ctx, &command, settings).await;
+        } else if let Interaction::Autocomplete(autocomplete) = interaction {
+            let suggestions = self.autocomplete.suggest(
+                self.spotify.as_ref(),
+                &autocomplete.data.name,
+                &autocomplete.data.options,
+                autocomplete.user.id,
+            ).await;
+
+            let suggestions = match suggestions {
+                Ok(Some(suggestions)) => suggestions,
+                // someone kept typing, so discord has already moved on
+                Ok(None) => return,
+                Err(why) => {
+                    error!("Cannot autocomplete: {}", String::from(why));
+                    vec![]
+                }
+            };
+
+            if let Err(why) = autocomplete
+                .create_autocomplete_response(&ctx.http, |response| {
+                    for suggestion in suggestions {
+                        response.add_string_choice(suggestion.name, suggestion.value);
+                    }
+                    response
+                })
+                .await
+            {
+                error!("Cannot respond to autocomplete: {}", why);
+            }
+        } else if let Interaction::MessageComponent(component) = interaction {
+            info!("Received component interaction: {:#?}", component);
+
+            let settings = self.config.guild(component.guild_id);
+
+            // picking a different search result just redraws the menu
+            if component.data.custom_id == commands::search::SELECT_ID {
+                let choices = commands::search::choices_from_message(&component.message.components);
+                let selected = component.data.values
+                    .first()
+                    .and_then(|uri| choices.iter().position(|choice| &choice.uri == uri))
+                    .unwrap_or(0);
+
+                if let Err(why) = component
+                    .create_interaction_response(&ctx.http, |response| {
+                        response
+                            .kind(InteractionResponseType::UpdateMessage)
+                            .interaction_response_data(|message| {
+                                message
+                                    .set_components(commands::search::components(&choices, selected))
+                                    .content(&component.message.content)
+                            })
+                    })
+                    .await
+                {
+                    error!("Cannot respond to component interaction: {}", why);
+                }
+                return;
+            }
+
+            // play and queue reply with a new message so the results stay usable
+            if let Some((action, uri)) = commands::search::parse_button(&component.data.custom_id) {
+                let name = match action {
+                    commands::search::Action::Play => "play",
+                    commands::search::Action::Queue => "queue add",
+                };
+
+                let allowed = permissions::check_in(settings, component.channel_id, name, component.user.id, component.member.as_ref());
+
+                if let Err(why) = allowed {
+                    if let Err(why) = component
+                        .create_interaction_response(&ctx.http, |response| {
+                            response
+                                .kind(InteractionResponseType::ChannelMessageWithSource)
+                                .interaction_response_data(|message| message.ephemeral(true).content(String::from(why)))
+                        })
+                        .await
+                    {
+                        error!("Cannot respond to component interaction: {}", why);
+                    }
+                    return;
+                }
+
+                // a whole album or playlist can take a while to queue
+                if let Err(why) = component
+                    .create_interaction_response(&ctx.http, |response| {
+                        response.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    })
+                    .await
+                {
+                    error!("Cannot defer component interaction: {}", why);
+                    return;
+                }
+
+                let sent = match self.run_search_action(&component, settings, action, uri).await {
+                    Ok(content) => component
+                        .edit_original_interaction_response(&ctx.http, |edit| edit.content(content))
+                        .await
+                        .map(|_| ()),
+                    Err(why) => {
+                        // errors only go to whoever pressed the button
+                        if let Err(why) = component.delete_original_interaction_response(&ctx.http).await {
+                            error!("Cannot delete deferred response: {}", why);
+                        }
+                        component
+                            .create_followup_message(&ctx.http, |message| message.ephemeral(true).content(String::from(why)))
+                            .await
+                            .map(|_| ())
+                    }
+                };
+
+                if let Err(why) = sent {
+                    error!("Cannot edit component interaction response: {}", why);
+                }
+                return;
+            }
+
+            // list pages redraw the list in place
+            if let Some(page) = commands::list::parse_button(&component.data.custom_id) {
+                let result = match permissions::check_in(settings, component.channel_id, "list", component.user.id, component.member.as_ref()) {
+                    Ok(()) => commands::list::page(self.spotify.as_ref(), &self.queue, page).await,
+                    Err(why) => Err(why),
+                };
+
+                let sent = match result {
+                    Ok(reply) => component
+                        .create_interaction_response(&ctx.http, |response| {
+                            response
+                                .kind(InteractionResponseType::UpdateMessage)
+                                .interaction_response_data(|message| reply.render(message))
+                        })
+                        .await,
+                    Err(why) => component
+                        .create_interaction_response(&ctx.http, |response| {
+                            response
+                                .kind(InteractionResponseType::ChannelMessageWithSource)
+                                .interaction_response_data(|message| message.ephemeral(true).content(String::from(why)))
+                        })
+                        .await,
+                };
+
+                if let Err(why) = sent {
+                    error!("Cannot respond to component interaction: {}", why);
+                }
+                return;
+            }
+
+            let vote_threshold = if permissions::is_dj(settings, component.user.id, component.member.as_ref()) {
+                VoteThreshold::Off
+            } else {
+                settings.skip_vote_threshold
+            };
+
+            let listeners = component.guild_id
+                .and_then(|guild_id| vote::voice_listeners(&ctx.cache, guild_id, component.user.id));
+
+            // status controls redraw the status message once they are done
+            if let Some(control) = commands::status::Control::from_custom_id(&component.data.custom_id) {
+                let allowed = permissions::check_control(
+                    settings,
+                    component.channel_id,
+                    control.command(),
+                    component.user.id,
+                    component.member.as_ref(),
+                );
+
+                if let Err(why) = allowed {
+                    if let Err(why) = component
+                        .create_interaction_response(&ctx.http, |response| {
+                            response
+                                .kind(InteractionResponseType::ChannelMessageWithSource)
+                                .interaction_response_data(|message| message.ephemeral(true).content(String::from(why)))
+                        })
+                        .await
+                    {
+                        error!("Cannot respond to component interaction: {}", why);
+                    }
+                    return;
+                }
+
+                // controls and the redraw take a few spotify calls, plus the settle time
+                if let Err(why) = component
+                    .create_interaction_response(&ctx.http, |response| {
+                        response.kind(InteractionResponseType::DeferredUpdateMessage)
+                    })
+                    .await
+                {
+                    error!("Cannot defer component interaction: {}", why);
+                    return;
+                }
+
+                // act on and redraw the same account, the presser's own in linked guilds
+                let result = match self.account_for(settings, "status", component.user.id).await {
+                    Ok((account, Some(queue))) if control == commands::status::Control::Next => commands::skip::vote(
+                        account.as_ref(),
+                        queue,
+                        &self.votes,
+                        vote_threshold,
+                        component.user.id,
+                        listeners,
+                    ).await.map(|note| (account, note)),
+                    Ok((account, _)) => commands::status::control(account.as_ref(), control)
+                        .await
+                        .map(|note| (account, note)),
+                    Err(why) => Err(why),
+                };
+
+                // a failed control leaves the status as it was, and only tells the presser
+                let (account, note) = match result {
+                    Ok(result) => result,
+                    Err(why) => {
+                        if let Err(why) = component
+                            .create_followup_message(&ctx.http, |message| message.ephemeral(true).content(String::from(why)))
+                            .await
+                        {
+                            error!("Cannot send follow-up message: {}", why);
+                        }
+                        return;
+                    }
+                };
+
+                tokio::time::sleep(commands::status::SETTLE).await;
+
+                let reply = match commands::status::run(&[], account.as_ref()).await {
+                    Ok(mut reply) => {
+                        reply.content = Some(note);
+                        reply
+                    }
+                    Err(why) => Response::text(format!("{}\n{}", note, String::from(why))),
+                };
+
+                if let Err(why) = component
+                    .edit_original_interaction_response(&ctx.http, |edit| reply.render_edit(edit))
+                    .await
+                {
+                    error!("Cannot edit component interaction response: {}", why);
+                }
+                return;
+            }
+
+            let content_result = match component.data.custom_id.as_str() {
+                commands::skip::VOTE_BUTTON_ID => match permissions::check_in(
+                    settings,
+                    component.channel_id,
+                    "skip",
+                    component.user.id,
+                    component.member.as_ref(),
+                ) {
+                    Ok(()) => commands::skip::vote(
+                        self.spotify.as_ref(),
+                        &self.queue,
+                        &self.votes,
+                        vote_threshold,
+                        component.user.id,
+                        listeners,
+                    ).await,
+                    Err(why) => Err(why),
+                },
                 _ => Err(CommandError::SimpleError("not implemented :(".to_string())),
             };
 
             let content = match content_result {
                 Ok(msg) => msg,
-                Err(why) => String::from(why),
+                Err(why) => {
+                    if let Err(why) = component
+                        .create_interaction_response(&ctx.http, |response| {
+                            response
+                                .kind(InteractionResponseType::ChannelMessageWithSource)
+                                .interaction_response_data(|message| message.ephemeral(true).content(String::from(why)))
+                        })
+                        .await
+                    {
+                        error!("Cannot respond to component interaction: {}", why);
+                    }
+                    return;
+                }
             };
 
-            if let Err(why) = command
+            let vote_open = self.votes.lock().await.is_open();
+
+            if let Err(why) = component
                 .create_interaction_response(&ctx.http, |response| {
                     response
-                        .kind(InteractionResponseType::ChannelMessageWithSource)
-                        .interaction_response_data(|message| message.content(content))
+                        .kind(InteractionResponseType::UpdateMessage)
+                        .interaction_response_data(|message| {
+                            if vote_open {
+                                message.components(|components| commands::skip::vote_button(components));
+                            } else {
+                                message.components(|components| components);
+                            }
+                            message.content(content)
+                        })
                 })
                 .await
             {
-                error!("Cannot respond to slash command: {}", why);
+                error!("Cannot respond to component interaction: {}", why);
             }
         }
     }
@@ -214,31 +932,17 @@ impl EventHandler for Handler {
     async fn ready(&self, ctx: Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
 
-        let guild_id = GuildId(
-            env::var("GUILD_ID")
-                .expect("Expected GUILD_ID in environment")
-                .parse()
-                .expect("GUILD_ID must be an integer"),
-        );
+        if self.config.global_commands {
+            let commands = Command::set_global_application_commands(&ctx.http, register_commands).await;
 
-        let commands = GuildId::set_application_commands(&guild_id, &ctx.http, |commands| {
-            commands
-                .create_application_command(|command| commands::search::register(command))
-                .create_application_command(|command| commands::play::register(command))
-                .create_application_command(|command| commands::queue::register(command))
-                .create_application_command(|command| commands::set::register(command))
-                .create_application_command(|command| commands::skip::register(command))
-                .create_application_command(|command| commands::list::register(command))
-                .create_application_command(|command| commands::pause::register(command))
-                .create_application_command(|command| commands::resume::register(command))
-                .create_application_command(|command| commands::connect::register(command))
-                .create_application_command(|command| commands::status::register(command))
-                .create_application_command(|command| commands::info::register(command))
-                .create_application_command(|command| commands::devices::register(command))
-        })
-        .await;
+            info!("I now have the following global slash commands: {:#?}", commands);
+        } else {
+            for guild_id in &self.config.guild_ids {
+                let commands = GuildId::set_application_commands(guild_id, &ctx.http, register_commands).await;
 
-        info!("I now have the following guild slash commands: {:#?}", commands);
+                info!("I now have the following guild slash commands in {}: {:#?}", guild_id, commands);
+            }
+        }
 
 //        let guild_command = Command::create_global_application_command(&ctx.http, |command| {
 //            commands::wonderful_command::register(command)
@@ -255,30 +959,103 @@ async fn main() {
 
     log_init().expect("log init failed");
 
+    let config = match config::Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(why) => {
+            error!("Config error: {}", why);
+            std::process::exit(1);
+        }
+    };
+
     // Spotify auth
-    let config = Config {
-        token_refreshing: true,
-        ..Default::default()
+    let spotify: Arc<dyn SpotifyBackend> = if config.features.fake_spotify {
+        info!("Using fake spotify backend");
+        Arc::new(FakeSpotify::with_demo_catalog())
+    } else {
+        // the token is cached so restarts don't need a new login
+        let client_config = Config {
+            token_refreshing: true,
+            token_cached: true,
+            cache_path: config.spotify.token_cache.clone(),
+            ..Default::default()
+        };
+
+        let creds = Credentials::new(&config.spotify.client_id, &config.spotify.client_secret);
+        let oauth = OAuth {
+            redirect_uri: config.spotify.redirect_uri.clone(),
+            scopes: scopes!("user-read-playback-state", "user-modify-playback-state"),
+            ..Default::default()
+        };
+
+        let spotify = Arc::new(AuthCodeSpotify::with_config(creds, oauth, client_config));
+
+        // the admin gets the login link over DM when the token can't be refreshed,
+        // otherwise it is prompted for on the terminal
+        let admin = config.admin_user.map(|user| auth::Admin {
+            http: Arc::new(Http::new(&config.discord_token)),
+            user,
+        });
+
+        if let Err(why) = auth::authenticate(&spotify, admin.as_ref()).await {
+            error!("Spotify login failed: {}", why);
+            std::process::exit(1);
+        }
+
+        auth::spawn_token_keeper(spotify.clone(), admin);
+
+        spotify
     };
 
-    let creds = Credentials::from_env().unwrap();
-    let oauth = OAuth::from_env(scopes!("user-read-playback-state", "user-modify-playback-state")).unwrap();
+    // Per-user accounts, linked with /link
+    let accounts = match &config.spotify.link_redirect_uri {
+        Some(redirect_uri) if !config.features.fake_spotify => {
+            let accounts = Arc::new(Accounts::new(
+                Credentials::new(&config.spotify.client_id, &config.spotify.client_secret),
+                redirect_uri.clone(),
+                config.spotify.user_token_dir.clone(),
+            ));
 
-    let spotify = AuthCodeSpotify::with_config(creds, oauth, config);
-    let url = spotify.get_authorize_url(false).unwrap();
+            // validated when the config was loaded
+            let uri = url::Url::parse(redirect_uri).expect("link redirect uri is valid");
+            let port = uri.port_or_known_default().expect("link redirect uri has a port");
+            accounts::spawn_listener(accounts.clone(), port, uri.path().to_string());
 
-    spotify
-        .prompt_for_token(&url)
-        .await
-        .expect("auth failed");
+            Some(accounts)
+        }
+        _ => None,
+    };
 
-    // Discord auth
-    // Configure the client with your Discord bot token in the environment.
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+    // Playback tracking
+    let events = playback::channel();
+    let queue = Arc::new(Mutex::new(LocalQueue::new()));
+
+    let (state, playback_state) = playback::state();
+
+    playback::spawn_poller(spotify.clone(), config.poll_interval, events.clone(), state);
+    local_queue::spawn_advancer(queue.clone(), spotify.clone(), events.subscribe());
+
+    // Skip votes
+    let votes = Arc::new(Mutex::new(SkipVotes::new()));
+    vote::spawn_vote_reset(votes.clone(), events.subscribe());
+
+    // spotifyd onevent hook, for instant track end notifications
+    if let Some(webhook) = &config.webhook {
+        webhook::spawn_listener(webhook.port, webhook.secret.clone(), events.clone());
+    }
 
+    // Discord auth
     // Build our client.
-    let mut client = Client::builder(token, GatewayIntents::empty())
-        .event_handler(Handler { spotify: spotify, })
+    let mut client = Client::builder(&config.discord_token, GatewayIntents::GUILDS | GatewayIntents::GUILD_VOICE_STATES)
+        .event_handler(Handler {
+            spotify,
+            queue,
+            votes,
+            config: config.clone(),
+            accounts,
+            autocomplete: Autocomplete::new(),
+            playback: playback_state,
+            live: Arc::new(LiveMessages::new()),
+        })
         .await
         .expect("Error creating client");
 
@@ -290,3 +1067,88 @@ async fn main() {
         error!("Client error: {:?}", why);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::api_error;
+
+    #[test]
+    fn sorts_spotify_errors_by_status_and_endpoint() {
+        assert!(matches!(CommandError::from(api_error(404, "/me/player/play", "")), CommandError::NoActiveDevice(_)));
+        assert!(matches!(CommandError::from(api_error(404, "/albums", "")), CommandError::NotFound(_)));
+        assert!(matches!(CommandError::from(api_error(401, "/me/player", "")), CommandError::AuthExpired(_)));
+        assert!(matches!(CommandError::from(api_error(403, "/me/player/pause", "")), CommandError::Forbidden(_)));
+        assert!(matches!(CommandError::from(api_error(502, "/search", "")), CommandError::SpotifyError(_)));
+    }
+
+    #[tokio::test]
+    async fn rate_limits_say_how_long_to_wait() {
+        let spotify = FakeSpotify::new();
+        spotify.state().retry_after = 30;
+        spotify.state().failures.push_back(429);
+
+        let error = CommandError::from(spotify.device().await.unwrap_err());
+
+        assert!(matches!(error, CommandError::RateLimited(wait, _) if wait == Duration::from_secs(30)));
+        assert_eq!(String::from(error), "Error: Spotify is rate limiting the bot, try again in 30s");
+    }
+
+    const TRACK: &str = "4uLU6hMCjMI75M1A2tKUQC";
+
+    fn track_uri(link: &str) -> Option<String> {
+        uri_from_link(link, SearchType::Track)
+    }
+
+    #[test]
+    fn reads_open_spotify_urls() {
+        let expected = Some(format!("spotify:track:{}", TRACK));
+
+        assert_eq!(track_uri(&format!("https://open.spotify.com/track/{}", TRACK)), expected);
+        assert_eq!(track_uri(&format!("https://open.spotify.com/track/{}?si=1a2b3c4d5e6f", TRACK)), expected);
+        assert_eq!(track_uri(&format!("https://open.spotify.com/intl-de/track/{}", TRACK)), expected);
+        assert_eq!(track_uri(&format!("https://open.spotify.com/embed/track/{}?utm_source=generator", TRACK)), expected);
+        assert_eq!(track_uri(&format!("open.spotify.com/track/{}/", TRACK)), expected);
+        assert_eq!(track_uri(&format!("  http://open.spotify.com/track/{}  ", TRACK)), expected);
+
+        assert_eq!(
+            track_uri("https://open.spotify.com/playlist/37i9dQZF1DXcBWIGoYBM5M?si=abc&pt=def"),
+            Some("spotify:playlist:37i9dQZF1DXcBWIGoYBM5M".to_string()),
+        );
+    }
+
+    #[test]
+    fn reads_spotify_uris() {
+        assert_eq!(track_uri("spotify:album:6XhjNHCyCDyyGJRM5mg40G"), Some("spotify:album:6XhjNHCyCDyyGJRM5mg40G".to_string()));
+        assert_eq!(
+            track_uri("spotify:user:someone:playlist:37i9dQZF1DXcBWIGoYBM5M"),
+            Some("spotify:playlist:37i9dQZF1DXcBWIGoYBM5M".to_string()),
+        );
+        assert_eq!(track_uri("spotify:show:37i9dQZF1DXcBWIGoYBM5M"), None);
+        assert_eq!(track_uri("spotify:track:tooshort"), None);
+    }
+
+    #[test]
+    fn bare_ids_take_the_chosen_type() {
+        assert_eq!(track_uri(TRACK), Some(format!("spotify:track:{}", TRACK)));
+        assert_eq!(uri_from_link(TRACK, SearchType::Album), Some(format!("spotify:album:{}", TRACK)));
+        assert_eq!(uri_from_link(TRACK, SearchType::Show), None);
+    }
+
+    #[test]
+    fn leaves_everything_else_to_search() {
+        assert_eq!(track_uri("never gonna give you up"), None);
+        assert_eq!(track_uri(&format!("https://example.com/track/{}", TRACK)), None);
+        assert_eq!(track_uri(&format!("https://open.spotify.com.example.com/track/{}", TRACK)), None);
+        assert_eq!(track_uri("https://open.spotify.com/track/"), None);
+        assert_eq!(track_uri("https://open.spotify.com/episode/4uLU6hMCjMI75M1A2tKUQC"), None);
+    }
+
+    #[test]
+    fn hides_raw_spotify_errors() {
+        let message = String::from(CommandError::from(api_error(500, "/me/player", "Server error")));
+
+        assert!(!message.contains("500"));
+        assert!(!message.contains("Server error"));
+    }
+}
@@ -0,0 +1,166 @@
+use serenity::model::guild::Member;
+use serenity::model::id::{ChannelId, UserId};
+
+use crate::CommandError;
+use crate::accounts;
+use crate::config::AccountMode;
+use crate::config::GuildSettings;
+use crate::vote::VoteThreshold;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    // only looks at playback, or only affects the person running it
+    ReadOnly,
+    // changes what the shared speaker is doing
+    Mutating,
+}
+
+pub fn access(command: &str) -> Access {
+    match command {
+        "status" | "list" | "search" | "devices" | "info" | "nowplaying" | "link" | "unlink" => Access::ReadOnly,
+        _ => Access::Mutating,
+    }
+}
+
+// Whether the guild limits mutating commands to DJs at all
+pub fn is_restricted(settings: &GuildSettings) -> bool {
+    settings.dj_role.is_some() || !settings.allowed_users.is_empty()
+}
+
+// Whether `user` can change anything in the queue, not just their own entries
+pub fn may_manage(settings: &GuildSettings, user: UserId, member: Option<&Member>) -> bool {
+    !is_restricted(settings) || is_dj(settings, user, member)
+}
+
+// Has the DJ role or is on the allow-list
+pub fn is_dj(settings: &GuildSettings, user: UserId, member: Option<&Member>) -> bool {
+    if settings.allowed_users.contains(&user) {
+        return true;
+    }
+
+    match (settings.dj_role, member) {
+        (Some(role), Some(member)) => member.roles.contains(&role),
+        _ => false,
+    }
+}
+
+// Checked before a command runs, so a denied command never reaches spotify
+pub fn check(
+    settings: &GuildSettings,
+    command: &str,
+    user: UserId,
+    member: Option<&Member>,
+) -> Result<(), CommandError> {
+    if access(command) == Access::ReadOnly || !is_restricted(settings) || is_dj(settings, user, member) {
+        return Ok(());
+    }
+
+    // linked accounts only change their owner's own player
+    if settings.account == AccountMode::Linked && accounts::uses_linked_account(command) {
+        return Ok(());
+    }
+
+    // anyone can remove what they queued, /queue remove checks the rest
+    if command == "queue remove" {
+        return Ok(());
+    }
+
+    // anyone can vote to skip, only skipping outright needs a DJ
+    if command == "skip" && settings.skip_vote_threshold != VoteThreshold::Off {
+        return Ok(());
+    }
+
+    Err(match settings.dj_role {
+        Some(role) => CommandError::SimpleError(format!("You need the <@&{}> role to use /{}", role, command)),
+        None => CommandError::SimpleError(format!("You aren't allowed to use /{}", command)),
+    })
+}
+
+// The channel check followed by the command check, for anything run from a
+// message in `channel`
+pub fn check_in(
+    settings: &GuildSettings,
+    channel: ChannelId,
+    command: &str,
+    user: UserId,
+    member: Option<&Member>,
+) -> Result<(), CommandError> {
+    if !settings.allows_channel(channel) {
+        return Err(CommandError::from("Fishify commands can't be used in this channel"));
+    }
+
+    check(settings, command, user, member)
+}
+
+// The check for a status control button. The buttons act on the account
+// /status shows, so in linked guilds they only change the presser's own
+// player, the same as the commands exempted in `check`.
+pub fn check_control(
+    settings: &GuildSettings,
+    channel: ChannelId,
+    command: &str,
+    user: UserId,
+    member: Option<&Member>,
+) -> Result<(), CommandError> {
+    if settings.account == AccountMode::Linked && accounts::uses_linked_account("status") {
+        return check_in(settings, channel, "status", user, member);
+    }
+
+    check_in(settings, channel, command, user, member)
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::model::id::RoleId;
+
+    use super::*;
+
+    const DJ: UserId = UserId(1);
+    const LISTENER: UserId = UserId(2);
+    const MUSIC: ChannelId = ChannelId(10);
+    const GENERAL: ChannelId = ChannelId(11);
+
+    fn restricted(account: AccountMode, skip_vote_threshold: VoteThreshold) -> GuildSettings {
+        GuildSettings {
+            default_device: None,
+            allowed_channels: vec![MUSIC],
+            dj_role: Some(RoleId(5)),
+            allowed_users: vec![DJ],
+            skip_vote_threshold,
+            account,
+        }
+    }
+
+    #[test]
+    fn controls_need_a_dj_on_the_shared_account() {
+        let settings = restricted(AccountMode::Shared, VoteThreshold::Count(2));
+
+        assert!(check_control(&settings, MUSIC, "set", DJ, None).is_ok());
+        assert!(check_control(&settings, MUSIC, "set", LISTENER, None).is_err());
+        assert!(check_control(&settings, MUSIC, "playback", LISTENER, None).is_err());
+        // the next button only votes
+        assert!(check_control(&settings, MUSIC, "skip", LISTENER, None).is_ok());
+    }
+
+    #[test]
+    fn controls_on_linked_accounts_are_the_pressers_own() {
+        let settings = restricted(AccountMode::Linked, VoteThreshold::Off);
+
+        assert!(check_control(&settings, MUSIC, "set", LISTENER, None).is_ok());
+        assert!(check_control(&settings, MUSIC, "skip", LISTENER, None).is_ok());
+        // but only in the allowed channels
+        assert!(check_control(&settings, GENERAL, "set", LISTENER, None).is_err());
+        assert!(check_control(&settings, GENERAL, "set", DJ, None).is_err());
+    }
+
+    #[test]
+    fn voting_to_skip_follows_the_threshold_and_channels() {
+        let voting = restricted(AccountMode::Shared, VoteThreshold::Count(2));
+        assert!(check_in(&voting, MUSIC, "skip", LISTENER, None).is_ok());
+        assert!(check_in(&voting, GENERAL, "skip", LISTENER, None).is_err());
+
+        let off = restricted(AccountMode::Shared, VoteThreshold::Off);
+        assert!(check_in(&off, MUSIC, "skip", LISTENER, None).is_err());
+        assert!(check_in(&off, MUSIC, "skip", DJ, None).is_ok());
+    }
+}
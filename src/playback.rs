@@ -0,0 +1,301 @@
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::{debug, error};
+use tokio::sync::broadcast;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use rspotify::{
+    model::context::CurrentPlaybackContext,
+    model::PlayableItem,
+    prelude::Id,
+};
+
+use crate::backend::SpotifyBackend;
+
+// How far from the end of a track playback can stop or move on and still count
+// as the track finishing, on top of the time since the last poll
+const END_SLACK: Duration = Duration::from_secs(2);
+
+// Changes in playback that other parts of the bot react to. Track ids are bare
+// spotify ids, as spotifyd reports them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaybackEvent {
+    TrackStarted { id: String },
+    // The poller only sends this when a track plays through to its end, not
+    // when it is skipped or replaced. spotifyd can't tell the two apart and
+    // reports any change away from a track as its end.
+    TrackEnded { id: String },
+    Paused,
+    Resumed,
+    DeviceChanged { id: Option<String>, name: String },
+}
+
+pub fn channel() -> broadcast::Sender<PlaybackEvent> {
+    let (sender, _) = broadcast::channel(32);
+    sender
+}
+
+// The playback seen by the latest poll, for anything that wants to show it
+// without asking spotify itself
+pub type PlaybackState = watch::Receiver<Option<CurrentPlaybackContext>>;
+
+pub fn state() -> (watch::Sender<Option<CurrentPlaybackContext>>, PlaybackState) {
+    watch::channel(None)
+}
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    item_id: Option<String>,
+    is_playing: bool,
+    device_id: Option<String>,
+    device_name: String,
+    progress: Duration,
+    duration: Duration,
+    taken_at: Instant,
+}
+
+impl Snapshot {
+    fn from_playback(playback: &CurrentPlaybackContext) -> Self {
+        let (item_id, duration) = match &playback.item {
+            Some(PlayableItem::Track(track)) => {
+                (track.id.as_ref().map(|id| id.id().to_string()), track.duration)
+            }
+            Some(PlayableItem::Episode(episode)) => {
+                (Some(episode.id.id().to_string()), episode.duration)
+            }
+            None => (None, Duration::ZERO),
+        };
+
+        Snapshot {
+            item_id,
+            is_playing: playback.is_playing,
+            device_id: playback.device.id.clone(),
+            device_name: playback.device.name.clone(),
+            progress: playback.progress.unwrap_or_default(),
+            duration,
+            taken_at: Instant::now(),
+        }
+    }
+
+    fn remaining(&self) -> Duration {
+        self.duration.saturating_sub(self.progress)
+    }
+
+    // Whether the track in this snapshot would have reached its end by `now`
+    fn finished_by(&self, now: Instant) -> bool {
+        self.is_playing && self.remaining() <= now.duration_since(self.taken_at) + END_SLACK
+    }
+}
+
+fn diff(previous: Option<&Snapshot>, next: Option<&Snapshot>) -> Vec<PlaybackEvent> {
+    let mut events = vec![];
+
+    let (previous, next) = match (previous, next) {
+        (None, None) => return events,
+        (None, Some(next)) => {
+            if let Some(id) = &next.item_id {
+                events.push(PlaybackEvent::TrackStarted { id: id.clone() });
+            }
+            return events;
+        }
+        (Some(previous), None) => {
+            // playback went away entirely, usually the device disconnecting
+            if let Some(id) = &previous.item_id {
+                if previous.finished_by(Instant::now()) {
+                    events.push(PlaybackEvent::TrackEnded { id: id.clone() });
+                }
+            }
+            return events;
+        }
+        (Some(previous), Some(next)) => (previous, next),
+    };
+
+    if previous.device_id != next.device_id {
+        events.push(PlaybackEvent::DeviceChanged {
+            id: next.device_id.clone(),
+            name: next.device_name.clone(),
+        });
+    }
+
+    let finished = previous.finished_by(next.taken_at);
+    let changed_track = previous.item_id != next.item_id;
+    // same track starting over, from repeat or from replaying it
+    let restarted = !changed_track && next.is_playing && next.progress < previous.progress && finished;
+    // spotify leaves the last track loaded but stopped when nothing follows it
+    let stopped_at_end = !changed_track && previous.is_playing && !next.is_playing && finished;
+
+    if changed_track || restarted || stopped_at_end {
+        if let (Some(id), true) = (&previous.item_id, finished) {
+            events.push(PlaybackEvent::TrackEnded { id: id.clone() });
+        }
+    }
+
+    if changed_track || restarted {
+        if let Some(id) = &next.item_id {
+            events.push(PlaybackEvent::TrackStarted { id: id.clone() });
+        }
+    }
+
+    if !stopped_at_end && previous.is_playing != next.is_playing {
+        if next.is_playing {
+            events.push(PlaybackEvent::Resumed);
+        } else {
+            events.push(PlaybackEvent::Paused);
+        }
+    }
+
+    events
+}
+
+// Poll current_playback every `interval`, sending an event for each change and
+// publishing what was seen to `state`. Polls sooner when the current track is
+// about to end so the end is noticed promptly.
+pub fn spawn_poller(
+    spotify: Arc<dyn SpotifyBackend>,
+    interval: Duration,
+    events: broadcast::Sender<PlaybackEvent>,
+    state: watch::Sender<Option<CurrentPlaybackContext>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut previous: Option<Snapshot> = None;
+
+        loop {
+            let next = match spotify.current_playback().await {
+                Ok(playback) => {
+                    let next = playback.as_ref().map(Snapshot::from_playback);
+                    state.send_replace(playback);
+                    next
+                }
+                Err(why) => {
+                    error!("Failed to poll playback: {}", why);
+                    tokio::time::sleep(interval).await;
+                    continue;
+                }
+            };
+
+            for event in diff(previous.as_ref(), next.as_ref()) {
+                debug!("Playback event: {:?}", event);
+                // an error only means nobody is subscribed right now
+                let _ = events.send(event);
+            }
+
+            let wait = match &next {
+                Some(snapshot) if snapshot.is_playing => {
+                    interval.min(snapshot.remaining() + Duration::from_millis(500)).max(Duration::from_secs(1))
+                }
+                _ => interval,
+            };
+
+            previous = next;
+            tokio::time::sleep(wait).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(id: &str, is_playing: bool, progress_secs: u64, taken_at: Instant) -> Snapshot {
+        Snapshot {
+            item_id: Some(id.to_string()),
+            is_playing,
+            device_id: Some("speaker".to_string()),
+            device_name: "Speaker".to_string(),
+            progress: Duration::from_secs(progress_secs),
+            duration: Duration::from_secs(180),
+            taken_at,
+        }
+    }
+
+    fn started(id: &str) -> PlaybackEvent {
+        PlaybackEvent::TrackStarted { id: id.to_string() }
+    }
+
+    fn ended(id: &str) -> PlaybackEvent {
+        PlaybackEvent::TrackEnded { id: id.to_string() }
+    }
+
+    #[test]
+    fn a_finished_track_moving_on_ends_it() {
+        let now = Instant::now();
+        let previous = snapshot("a", true, 178, now);
+        let next = snapshot("b", true, 1, now + Duration::from_secs(3));
+
+        assert_eq!(diff(Some(&previous), Some(&next)), [ended("a"), started("b")]);
+    }
+
+    #[test]
+    fn a_finished_track_starting_over_ends_it() {
+        let now = Instant::now();
+        let previous = snapshot("a", true, 179, now);
+        let next = snapshot("a", true, 2, now + Duration::from_secs(3));
+
+        assert_eq!(diff(Some(&previous), Some(&next)), [ended("a"), started("a")]);
+    }
+
+    #[test]
+    fn stopping_at_the_end_ends_the_track_without_pausing() {
+        let now = Instant::now();
+        let previous = snapshot("a", true, 179, now);
+        let next = snapshot("a", false, 0, now + Duration::from_secs(3));
+
+        assert_eq!(diff(Some(&previous), Some(&next)), [ended("a")]);
+    }
+
+    #[test]
+    fn skipping_mid_track_does_not_end_it() {
+        let now = Instant::now();
+        let previous = snapshot("a", true, 30, now);
+        let next = snapshot("b", true, 1, now + Duration::from_secs(3));
+
+        assert_eq!(diff(Some(&previous), Some(&next)), [started("b")]);
+
+        // seeking back within the same track is not a restart either
+        let next = snapshot("a", true, 5, now + Duration::from_secs(3));
+        assert_eq!(diff(Some(&previous), Some(&next)), []);
+    }
+
+    #[test]
+    fn pausing_and_resuming() {
+        let now = Instant::now();
+        let playing = snapshot("a", true, 30, now);
+        let paused = snapshot("a", false, 33, now + Duration::from_secs(3));
+        let resumed = snapshot("a", true, 33, now + Duration::from_secs(6));
+
+        assert_eq!(diff(Some(&playing), Some(&paused)), [PlaybackEvent::Paused]);
+        assert_eq!(diff(Some(&paused), Some(&resumed)), [PlaybackEvent::Resumed]);
+
+        // a paused track near its end is not finishing
+        let paused = snapshot("a", false, 179, now);
+        let later = snapshot("a", false, 179, now + Duration::from_secs(60));
+        assert_eq!(diff(Some(&paused), Some(&later)), []);
+    }
+
+    #[test]
+    fn moving_to_another_device() {
+        let now = Instant::now();
+        let previous = snapshot("a", true, 30, now);
+        let mut next = snapshot("a", true, 33, now + Duration::from_secs(3));
+        next.device_id = Some("phone".to_string());
+        next.device_name = "Phone".to_string();
+
+        assert_eq!(
+            diff(Some(&previous), Some(&next)),
+            [PlaybackEvent::DeviceChanged { id: Some("phone".to_string()), name: "Phone".to_string() }],
+        );
+    }
+
+    #[test]
+    fn playback_appearing_and_going_away() {
+        let now = Instant::now();
+        let playing = snapshot("a", true, 30, now);
+
+        assert_eq!(diff(None, None), []);
+        assert_eq!(diff(None, Some(&playing)), [started("a")]);
+        assert_eq!(diff(Some(&playing), None), []);
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use rspotify::{
+    model::enums::types::Type,
+    model::context::Context,
+    model::idtypes::AlbumId,
+    model::idtypes::PlaylistId,
+    model::PlayableItem,
+    prelude::Id,
+};
+
+use crate::CommandError;
+use crate::backend::SpotifyBackend;
+
+// Contexts are searched a page at a time for the current track, give up after
+// this many pages
+const MAX_PAGES: u32 = 10;
+const PAGE_SIZE: u32 = 50;
+
+// A track from an album or playlist, flattened so both look the same
+#[derive(Debug, Clone)]
+pub struct ContextTrack {
+    pub id: Option<String>,
+    pub name: String,
+    pub artist: String,
+    pub duration: Duration,
+}
+
+// 0 based index of a track within an album or playlist, if it can be found
+pub async fn position<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    context: &Context,
+    track_id: &str,
+) -> Result<Option<u32>, CommandError> {
+    for page_number in 0..MAX_PAGES {
+        let offset = page_number * PAGE_SIZE;
+        let (tracks, total) = tracks(spotify, context, offset, PAGE_SIZE).await?;
+
+        let index = tracks
+            .iter()
+            .position(|track| track.id.as_deref() == Some(track_id));
+
+        if let Some(index) = index {
+            return Ok(Some(offset + index as u32));
+        }
+
+        if tracks.is_empty() || offset + PAGE_SIZE >= total {
+            break;
+        }
+    }
+
+    Ok(None)
+}
+
+// Up to `limit` tracks of an album or playlist starting at `offset`, along
+// with how many tracks it has in total. Other contexts have no fixed order
+// and come back empty.
+pub async fn tracks<S: SpotifyBackend + ?Sized>(
+    spotify: &S,
+    context: &Context,
+    offset: u32,
+    limit: u32,
+) -> Result<(Vec<ContextTrack>, u32), CommandError> {
+    match context._type {
+        Type::Album => {
+            let page = spotify
+                .album_tracks(AlbumId::from_uri(&context.uri)?, Some(limit), Some(offset))
+                .await?;
+
+            let tracks = page.items
+                .into_iter()
+                .map(|track| ContextTrack {
+                    id: track.id.map(|id| id.id().to_string()),
+                    artist: track.artists.first().map(|artist| artist.name.clone()).unwrap_or_default(),
+                    name: track.name,
+                    duration: track.duration,
+                })
+                .collect();
+            Ok((tracks, page.total))
+        }
+        Type::Playlist => {
+            let page = spotify
+                .playlist_items(PlaylistId::from_uri(&context.uri)?, Some(limit), Some(offset))
+                .await?;
+
+            let tracks = page.items
+                .into_iter()
+                .filter_map(|item| match item.track? {
+                    PlayableItem::Track(track) => Some(ContextTrack {
+                        id: track.id.map(|id| id.id().to_string()),
+                        artist: track.artists.first().map(|artist| artist.name.clone()).unwrap_or_default(),
+                        name: track.name,
+                        duration: track.duration,
+                    }),
+                    PlayableItem::Episode(episode) => Some(ContextTrack {
+                        id: Some(episode.id.id().to_string()),
+                        artist: episode.show.name,
+                        name: episode.name,
+                        duration: episode.duration,
+                    }),
+                })
+                .collect();
+            Ok((tracks, page.total))
+        }
+        _ => Ok((vec![], 0)),
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use serenity::builder::{CreateComponents, CreateEmbed, CreateInteractionResponseData, EditInteractionResponse};
+use serenity::utils::Colour;
+
+use rspotify::model::PlayableItem;
+
+use crate::format_duration;
+
+pub const PLAYING_COLOUR: Colour = Colour(0x1DB954);
+pub const PAUSED_COLOUR: Colour = Colour(0x747F8D);
+
+const PROGRESS_BAR_LENGTH: usize = 20;
+
+// discord's limit on message content
+const MAX_CONTENT_LENGTH: usize = 2000;
+
+// What a command replies with: text, an embed, or both, plus any components
+#[derive(Debug, Clone, Default)]
+pub struct Response {
+    pub content: Option<String>,
+    pub embed: Option<CreateEmbed>,
+    pub components: Option<CreateComponents>,
+}
+
+impl Response {
+    pub fn text(content: impl Into<String>) -> Self {
+        Response {
+            content: Some(content.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn embed(embed: CreateEmbed) -> Self {
+        Response {
+            embed: Some(embed),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_components(mut self, components: CreateComponents) -> Self {
+        self.components = Some(components);
+        self
+    }
+
+    // Cut the content down to what fits in one message, returning the rest
+    // split into messages of their own
+    pub fn split_overflow(&mut self) -> Vec<String> {
+        let content = match self.content.take() {
+            Some(content) => content,
+            None => return vec![],
+        };
+
+        let mut messages = split_content(&content, MAX_CONTENT_LENGTH).into_iter();
+        self.content = messages.next();
+        messages.collect()
+    }
+
+    // Fill in an interaction response or message update
+    pub fn render<'a, 'b>(self, message: &'a mut CreateInteractionResponseData<'b>) -> &'a mut CreateInteractionResponseData<'b> {
+        // an update has to replace what was there before
+        message.content(self.content.unwrap_or_default());
+        message.set_embeds(self.embed);
+        if let Some(components) = self.components {
+            message.set_components(components);
+        }
+        message
+    }
+
+    // Fill in the edit that completes a deferred response
+    pub fn render_edit(self, edit: &mut EditInteractionResponse) -> &mut EditInteractionResponse {
+        edit.content(self.content.unwrap_or_default());
+        edit.set_embeds(self.embed.into_iter().collect());
+        if let Some(components) = self.components {
+            edit.components(|existing| {
+                *existing = components;
+                existing
+            });
+        }
+        edit
+    }
+}
+
+impl From<String> for Response {
+    fn from(content: String) -> Self {
+        Response::text(content)
+    }
+}
+
+pub fn state_colour(is_playing: bool) -> Colour {
+    if is_playing {
+        PLAYING_COLOUR
+    } else {
+        PAUSED_COLOUR
+    }
+}
+
+// ▰▰▰▰▱▱▱▱ 1:23 / 3:45
+pub fn progress_bar(progress: Duration, duration: Duration) -> String {
+    let filled = if duration.is_zero() {
+        0
+    } else {
+        ((progress.as_secs_f64() / duration.as_secs_f64()) * PROGRESS_BAR_LENGTH as f64).round() as usize
+    }.min(PROGRESS_BAR_LENGTH);
+
+    format!(
+        "{}{} {} / {}",
+        "\u{25B0}".repeat(filled),
+        "\u{25B1}".repeat(PROGRESS_BAR_LENGTH - filled),
+        format_duration(progress),
+        format_duration(duration),
+    )
+}
+
+// The track or episode playing: title linking to spotify, art, artists and
+// a progress bar, coloured by whether it is playing
+pub fn item_embed(item: &PlayableItem, progress: Option<Duration>, is_playing: bool) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed.colour(state_colour(is_playing));
+
+    let duration = match item {
+        PlayableItem::Track(track) => {
+            embed.title(&track.name);
+            if let Some(url) = track.external_urls.get("spotify") {
+                embed.url(url);
+            }
+            if let Some(image) = track.album.images.first() {
+                embed.thumbnail(&image.url);
+            }
+
+            let artists: Vec<&str> = track.artists.iter().map(|artist| artist.name.as_str()).collect();
+            embed.field("Artist", artists.join(", "), true);
+            embed.field("Album", &track.album.name, true);
+
+            track.duration
+        }
+        PlayableItem::Episode(episode) => {
+            embed.title(&episode.name);
+            if let Some(url) = episode.external_urls.get("spotify") {
+                embed.url(url);
+            }
+            if let Some(image) = episode.images.first() {
+                embed.thumbnail(&image.url);
+            }
+
+            embed.field("Show", &episode.show.name, true);
+
+            episode.duration
+        }
+    };
+
+    if let Some(progress) = progress {
+        embed.description(progress_bar(progress, duration));
+    }
+
+    embed
+}
+
+// Cut `string` down to at most `limit` characters, ending it with an ellipsis
+// when anything had to go
+pub fn truncate(string: &str, limit: usize) -> String {
+    if string.chars().count() <= limit {
+        return string.to_string();
+    }
+    let mut truncated: String = string.chars().take(limit - 1).collect();
+    truncated.push('\u{2026}');
+    truncated
+}
+
+// Split `content` into pieces of at most `limit` characters, breaking between
+// lines where possible
+fn split_content(content: &str, limit: usize) -> Vec<String> {
+    let mut messages: Vec<String> = vec![];
+    let mut current = String::new();
+    let mut current_length = 0;
+
+    for line in content.split_inclusive('\n') {
+        let mut line = line;
+        let mut line_length = line.chars().count();
+
+        if current_length + line_length > limit && !current.is_empty() {
+            messages.push(std::mem::take(&mut current));
+            current_length = 0;
+        }
+
+        // a single line too long for a message is broken up anywhere
+        while line_length > limit {
+            let split_at = line.char_indices().nth(limit).map_or(line.len(), |(index, _)| index);
+            messages.push(line[..split_at].to_string());
+            line = &line[split_at..];
+            line_length -= limit;
+        }
+
+        current.push_str(line);
+        current_length += line_length;
+    }
+
+    if !current.is_empty() {
+        messages.push(current);
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_by_characters() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("exactly10!", 10), "exactly10!");
+        assert_eq!(truncate("a bit too long", 10), "a bit too\u{2026}");
+        assert_eq!(truncate("\u{e9}\u{e9}\u{e9}\u{e9}", 3), "\u{e9}\u{e9}\u{2026}");
+    }
+}
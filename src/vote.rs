@@ -0,0 +1,250 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use serenity::cache::Cache;
+use serenity::model::id::{GuildId, UserId};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::playback::PlaybackEvent;
+
+// How many votes it takes to skip a track
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VoteThreshold {
+    // skipping is immediate, as before votes existed
+    Off,
+    Count(usize),
+    // share of the listeners in the voice channel, between 0 and 1
+    Fraction(f64),
+}
+
+impl VoteThreshold {
+    // Accepts "off", a count like "3", or a fraction like "0.5" or "50%"
+    pub fn parse(string: &str) -> Result<Self, String> {
+        let string = string.trim();
+
+        if string.eq_ignore_ascii_case("off") {
+            return Ok(VoteThreshold::Off);
+        }
+
+        let fraction = match string.strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f64>().map(|percent| percent / 100.0).ok(),
+            None if string.contains('.') => string.parse::<f64>().ok(),
+            None => {
+                return match string.parse::<usize>() {
+                    Ok(count) if count > 0 => Ok(VoteThreshold::Count(count)),
+                    _ => Err(format!("Invalid vote threshold '{}'", string)),
+                };
+            }
+        };
+
+        match fraction {
+            Some(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(VoteThreshold::Fraction(fraction)),
+            _ => Err(format!("Invalid vote threshold '{}'", string)),
+        }
+    }
+
+    // Votes needed given how many people are listening, if it is known
+    pub fn required(&self, listeners: Option<usize>) -> Option<usize> {
+        match (self, listeners) {
+            (VoteThreshold::Off, _) => Some(1),
+            (VoteThreshold::Count(count), _) => Some(*count),
+            (VoteThreshold::Fraction(fraction), Some(listeners)) => {
+                Some(((listeners as f64 * fraction).ceil() as usize).max(1))
+            }
+            (VoteThreshold::Fraction(_), None) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoteResult {
+    Counted { votes: usize, required: usize },
+    AlreadyVoted { votes: usize, required: usize },
+    Passed,
+}
+
+#[derive(Debug)]
+struct SkipVote {
+    track_id: String,
+    voters: HashSet<UserId>,
+}
+
+// The open vote to skip the current track, if there is one
+#[derive(Debug, Default)]
+pub struct SkipVotes {
+    current: Option<SkipVote>,
+}
+
+impl SkipVotes {
+    pub fn new() -> Self {
+        SkipVotes { current: None }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.current.is_some()
+    }
+
+    // Count a vote against `track_id`. A vote left over from an earlier track
+    // is thrown away first.
+    pub fn vote(&mut self, track_id: &str, user: UserId, required: usize) -> VoteResult {
+        if self.current.as_ref().map(|vote| vote.track_id != track_id).unwrap_or(true) {
+            self.current = Some(SkipVote {
+                track_id: track_id.to_string(),
+                voters: HashSet::new(),
+            });
+        }
+
+        let vote = self.current.as_mut().expect("vote was just opened");
+        let counted = vote.voters.insert(user);
+        let votes = vote.voters.len();
+
+        if votes >= required {
+            self.current = None;
+            VoteResult::Passed
+        } else if counted {
+            VoteResult::Counted { votes, required }
+        } else {
+            VoteResult::AlreadyVoted { votes, required }
+        }
+    }
+
+    // Drop the open vote unless it is for `track_id`
+    pub fn retain_track(&mut self, track_id: &str) {
+        if self.current.as_ref().map(|vote| vote.track_id != track_id).unwrap_or(false) {
+            self.current = None;
+        }
+    }
+}
+
+// Number of people, bots aside, in the same voice channel as `user`. None if
+// they are not in one.
+pub fn voice_listeners(cache: &Cache, guild_id: GuildId, user: UserId) -> Option<usize> {
+    let guild = cache.guild(guild_id)?;
+    let channel_id = guild.voice_states.get(&user)?.channel_id?;
+
+    Some(
+        guild.voice_states
+            .values()
+            .filter(|state| state.channel_id == Some(channel_id))
+            .filter(|state| !state.member.as_ref().map(|member| member.user.bot).unwrap_or(false))
+            .count()
+    )
+}
+
+// Throw away votes for a track once something else starts playing
+pub fn spawn_vote_reset(
+    votes: Arc<Mutex<SkipVotes>>,
+    mut events: broadcast::Receiver<PlaybackEvent>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(PlaybackEvent::TrackStarted { id }) => votes.lock().await.retain_track(&id),
+                Ok(_) => (),
+                Err(RecvError::Lagged(_)) => (),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: UserId = UserId(1);
+    const BOB: UserId = UserId(2);
+    const CAROL: UserId = UserId(3);
+
+    #[test]
+    fn parses_thresholds() {
+        assert_eq!(VoteThreshold::parse("off"), Ok(VoteThreshold::Off));
+        assert_eq!(VoteThreshold::parse(" OFF "), Ok(VoteThreshold::Off));
+        assert_eq!(VoteThreshold::parse("3"), Ok(VoteThreshold::Count(3)));
+        assert_eq!(VoteThreshold::parse("50%"), Ok(VoteThreshold::Fraction(0.5)));
+        assert_eq!(VoteThreshold::parse("100 %"), Ok(VoteThreshold::Fraction(1.0)));
+        assert_eq!(VoteThreshold::parse("0.25"), Ok(VoteThreshold::Fraction(0.25)));
+    }
+
+    #[test]
+    fn rejects_bad_thresholds() {
+        for threshold in ["", "0", "-1", "0%", "150%", "1.5", "0.0", "half", "3 votes"] {
+            assert!(VoteThreshold::parse(threshold).is_err(), "{:?} was accepted", threshold);
+        }
+    }
+
+    #[test]
+    fn required_votes() {
+        assert_eq!(VoteThreshold::Off.required(None), Some(1));
+        assert_eq!(VoteThreshold::Count(3).required(None), Some(3));
+        assert_eq!(VoteThreshold::Count(3).required(Some(10)), Some(3));
+
+        let half = VoteThreshold::Fraction(0.5);
+        assert_eq!(half.required(Some(4)), Some(2));
+        assert_eq!(half.required(Some(5)), Some(3));
+        // someone always has to vote, even alone or with nobody counted
+        assert_eq!(half.required(Some(1)), Some(1));
+        assert_eq!(half.required(Some(0)), Some(1));
+        // outside a voice channel there is nothing to take a share of
+        assert_eq!(half.required(None), None);
+    }
+
+    #[test]
+    fn passes_once_enough_people_vote() {
+        let mut votes = SkipVotes::new();
+
+        assert_eq!(votes.vote("a", ALICE, 3), VoteResult::Counted { votes: 1, required: 3 });
+        assert_eq!(votes.vote("a", BOB, 3), VoteResult::Counted { votes: 2, required: 3 });
+        assert_eq!(votes.vote("a", CAROL, 3), VoteResult::Passed);
+        assert!(!votes.is_open());
+
+        // the next vote starts from scratch
+        assert_eq!(votes.vote("a", ALICE, 3), VoteResult::Counted { votes: 1, required: 3 });
+    }
+
+    #[test]
+    fn voting_twice_counts_once() {
+        let mut votes = SkipVotes::new();
+
+        votes.vote("a", ALICE, 2);
+        assert_eq!(votes.vote("a", ALICE, 2), VoteResult::AlreadyVoted { votes: 1, required: 2 });
+        assert_eq!(votes.vote("a", BOB, 2), VoteResult::Passed);
+    }
+
+    #[test]
+    fn a_new_track_resets_the_vote() {
+        let mut votes = SkipVotes::new();
+
+        votes.vote("a", ALICE, 2);
+        votes.retain_track("a");
+        assert!(votes.is_open());
+        votes.retain_track("b");
+        assert!(!votes.is_open());
+
+        // votes for a track that has since changed don't carry over either
+        votes.vote("b", ALICE, 2);
+        assert_eq!(votes.vote("c", BOB, 2), VoteResult::Counted { votes: 1, required: 2 });
+    }
+
+    #[tokio::test]
+    async fn track_starts_reset_the_vote() {
+        let votes = Arc::new(Mutex::new(SkipVotes::new()));
+        votes.lock().await.vote("a", ALICE, 2);
+
+        let events = broadcast::channel(8).0;
+        let reset = spawn_vote_reset(votes.clone(), events.subscribe());
+
+        events.send(PlaybackEvent::Paused).unwrap();
+        events.send(PlaybackEvent::TrackStarted { id: "a".to_string() }).unwrap();
+        events.send(PlaybackEvent::TrackStarted { id: "b".to_string() }).unwrap();
+
+        // closing the channel lets the reset finish what it was sent
+        drop(events);
+        reset.await.unwrap();
+
+        assert!(!votes.lock().await.is_open());
+    }
+}
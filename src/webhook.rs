@@ -0,0 +1,207 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use log::{debug, error, info, warn};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+use crate::playback::PlaybackEvent;
+
+const SECRET_HEADER: &str = "x-fishify-secret";
+
+// Fields spotifyd passes to its onevent hook as environment variables. The
+// hook script forwards them as a form encoded body, eg:
+//
+//   curl -X POST -H "X-Fishify-Secret: $SECRET" \
+//     -d "PLAYER_EVENT=$PLAYER_EVENT&TRACK_ID=$TRACK_ID&OLD_TRACK_ID=$OLD_TRACK_ID" \
+//     http://bot:port/spotifyd
+#[derive(Debug, Default)]
+struct SpotifydEvent {
+    player_event: String,
+    track_id: Option<String>,
+    old_track_id: Option<String>,
+}
+
+impl SpotifydEvent {
+    fn parse(form: &[u8]) -> Option<Self> {
+        let mut event = SpotifydEvent::default();
+
+        for (key, value) in url::form_urlencoded::parse(form) {
+            let value = Some(value.into_owned()).filter(|value| !value.is_empty());
+            match key.as_ref() {
+                "PLAYER_EVENT" => event.player_event = value.unwrap_or_default(),
+                "TRACK_ID" => event.track_id = value,
+                "OLD_TRACK_ID" => event.old_track_id = value,
+                _ => (),
+            }
+        }
+
+        if event.player_event.is_empty() {
+            None
+        } else {
+            Some(event)
+        }
+    }
+
+    fn into_events(self) -> Vec<PlaybackEvent> {
+        match (self.player_event.as_str(), self.track_id) {
+            ("endoftrack", Some(id)) => vec![PlaybackEvent::TrackEnded { id }],
+            // a change away from another track means that one is over
+            ("change", Some(id)) => match self.old_track_id {
+                Some(old) if old != id => vec![
+                    PlaybackEvent::TrackEnded { id: old },
+                    PlaybackEvent::TrackStarted { id },
+                ],
+                _ => vec![PlaybackEvent::TrackStarted { id }],
+            },
+            ("start" | "load", Some(id)) => vec![PlaybackEvent::TrackStarted { id }],
+            ("play", _) => vec![PlaybackEvent::Resumed],
+            ("pause" | "stop", _) => vec![PlaybackEvent::Paused],
+            _ => vec![],
+        }
+    }
+}
+
+async fn handle(
+    request: Request<Body>,
+    secret: Arc<str>,
+    events: broadcast::Sender<PlaybackEvent>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::POST || request.uri().path() != "/spotifyd" {
+        return Ok(status(StatusCode::NOT_FOUND));
+    }
+
+    let authorized = request
+        .headers()
+        .get(SECRET_HEADER)
+        .map(|value| value.as_bytes() == secret.as_bytes())
+        .unwrap_or(false);
+
+    if !authorized {
+        warn!("Rejected webhook request with missing or wrong secret");
+        return Ok(status(StatusCode::UNAUTHORIZED));
+    }
+
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(why) => {
+            error!("Failed to read webhook body: {}", why);
+            return Ok(status(StatusCode::BAD_REQUEST));
+        }
+    };
+
+    let event = match SpotifydEvent::parse(&body) {
+        Some(event) => event,
+        None => return Ok(status(StatusCode::BAD_REQUEST)),
+    };
+
+    debug!("Received spotifyd event: {:?}", event);
+
+    for event in event.into_events() {
+        // an error only means nobody is subscribed right now
+        let _ = events.send(event);
+    }
+
+    Ok(status(StatusCode::NO_CONTENT))
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = code;
+    response
+}
+
+// Listen for spotifyd onevent hooks, turning them into playback events
+pub fn spawn_listener(
+    port: u16,
+    secret: String,
+    events: broadcast::Sender<PlaybackEvent>,
+) -> JoinHandle<()> {
+    let secret: Arc<str> = secret.into();
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+
+    tokio::spawn(async move {
+        let make_service = make_service_fn(move |_connection| {
+            let secret = secret.clone();
+            let events = events.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    handle(request, secret.clone(), events.clone())
+                }))
+            }
+        });
+
+        let server = match Server::try_bind(&address) {
+            Ok(server) => server,
+            Err(why) => {
+                error!("Failed to bind webhook listener on {}: {}", address, why);
+                return;
+            }
+        };
+
+        info!("Listening for spotifyd events on {}", address);
+
+        if let Err(why) = server.serve(make_service).await {
+            error!("Webhook listener error: {}", why);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(form: &str) -> Vec<PlaybackEvent> {
+        SpotifydEvent::parse(form.as_bytes()).map(SpotifydEvent::into_events).unwrap_or_default()
+    }
+
+    #[test]
+    fn parses_the_hook_fields() {
+        let event = SpotifydEvent::parse(b"PLAYER_EVENT=change&TRACK_ID=abc&OLD_TRACK_ID=def&OTHER=1").unwrap();
+
+        assert_eq!(event.player_event, "change");
+        assert_eq!(event.track_id.as_deref(), Some("abc"));
+        assert_eq!(event.old_track_id.as_deref(), Some("def"));
+    }
+
+    #[test]
+    fn missing_fields_are_empty() {
+        assert!(SpotifydEvent::parse(b"").is_none());
+        assert!(SpotifydEvent::parse(b"TRACK_ID=abc").is_none());
+        assert!(SpotifydEvent::parse(b"PLAYER_EVENT=&TRACK_ID=abc").is_none());
+
+        // spotifyd leaves variables it has nothing for empty
+        let event = SpotifydEvent::parse(b"PLAYER_EVENT=stop&TRACK_ID=&OLD_TRACK_ID=").unwrap();
+        assert_eq!(event.track_id, None);
+        assert_eq!(event.old_track_id, None);
+    }
+
+    #[test]
+    fn decodes_form_values() {
+        let event = SpotifydEvent::parse(b"PLAYER_EVENT=end%6Fftrack&TRACK_ID=a+b%2Fc").unwrap();
+
+        assert_eq!(event.player_event, "endoftrack");
+        assert_eq!(event.track_id.as_deref(), Some("a b/c"));
+    }
+
+    #[test]
+    fn turns_each_event_into_playback_events() {
+        let started = |id: &str| PlaybackEvent::TrackStarted { id: id.to_string() };
+        let ended = |id: &str| PlaybackEvent::TrackEnded { id: id.to_string() };
+
+        assert_eq!(events("PLAYER_EVENT=endoftrack&TRACK_ID=abc"), [ended("abc")]);
+        assert_eq!(events("PLAYER_EVENT=endoftrack"), []);
+        assert_eq!(events("PLAYER_EVENT=start&TRACK_ID=abc"), [started("abc")]);
+        assert_eq!(events("PLAYER_EVENT=load&TRACK_ID=abc"), [started("abc")]);
+        assert_eq!(events("PLAYER_EVENT=change&TRACK_ID=abc"), [started("abc")]);
+        assert_eq!(events("PLAYER_EVENT=change&TRACK_ID=abc&OLD_TRACK_ID=def"), [ended("def"), started("abc")]);
+        assert_eq!(events("PLAYER_EVENT=change&TRACK_ID=abc&OLD_TRACK_ID=abc"), [started("abc")]);
+        assert_eq!(events("PLAYER_EVENT=play&TRACK_ID=abc"), [PlaybackEvent::Resumed]);
+        assert_eq!(events("PLAYER_EVENT=pause"), [PlaybackEvent::Paused]);
+        assert_eq!(events("PLAYER_EVENT=stop"), [PlaybackEvent::Paused]);
+        assert_eq!(events("PLAYER_EVENT=volumeset"), []);
+    }
+}